@@ -0,0 +1,1276 @@
+use crate::expr::{self, ExprErr};
+use crate::ins::{Ins, Rounding};
+use crate::object::Object;
+use crate::word::Word;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A problem found while assembling, pointing at the offending token.
+///
+/// `line` and `col` are 1-based; an error that is not tied to a location in
+/// the source (e.g. the file could not be read) has both set to 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+    pub token: String,
+    pub msg: String,
+    pub source_line: String,
+    /// Another location involved, e.g. the macro call an error in a macro
+    /// body came from. Notes can have notes of their own.
+    pub note: Option<Box<AsmError>>,
+}
+
+impl AsmError {
+    fn render(&self, f: &mut fmt::Formatter<'_>, level: &str) -> fmt::Result {
+        writeln!(f, "{level}: {}", self.msg)?;
+
+        if self.line == 0 {
+            return write!(f, " --> {}", self.file);
+        }
+
+        let gutter = " ".repeat(self.line.to_string().len());
+
+        // Keep tabs so the carets line up with the source as printed.
+        let pad = self
+            .source_line
+            .chars()
+            .take(self.col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let carets = "^".repeat(self.token.chars().count().max(1));
+
+        writeln!(f, "{gutter}--> {}:{}:{}", self.file, self.line, self.col)?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        write!(f, "{gutter} | {pad}{carets}")
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f, "error")?;
+
+        let mut note = &self.note;
+        while let Some(n) = note {
+            writeln!(f)?;
+            n.render(f, "note")?;
+            note = &n.note;
+        }
+
+        Ok(())
+    }
+}
+
+/// Renders every error, one after another, followed by a summary line.
+pub fn format_errors(errors: &[AsmError]) -> String {
+    let mut out = String::new();
+
+    for e in errors {
+        out.push_str(&e.to_string());
+        out.push_str("\n\n");
+    }
+
+    match errors.len() {
+        1 => out.push_str("error: aborting due to 1 previous error"),
+        n => out.push_str(&format!("error: aborting due to {n} previous errors")),
+    }
+
+    out
+}
+
+struct Token<'a> {
+    text: &'a str,
+    col: usize,
+}
+
+/// Somewhere a line passed through on its way into the program, an
+/// `.include` or a macro call, shown as a note under errors on that line.
+#[derive(Debug, Clone)]
+struct Site {
+    file: String,
+    line: usize,
+    col: usize,
+    token: String,
+    source_line: String,
+    note: String,
+}
+
+struct Line<'a> {
+    file: &'a str,
+    number: usize,
+    text: &'a str,
+    tokens: Vec<Token<'a>>,
+    /// The includes and macro calls this line came through, outermost first.
+    sites: &'a [Site],
+}
+
+impl<'a> Line<'a> {
+    fn new(file: &'a str, number: usize, text: &'a str, sites: &'a [Site]) -> Self {
+        Line {
+            file,
+            number,
+            text,
+            tokens: tokenize(text),
+            sites,
+        }
+    }
+
+    fn error(&self, token: &Token, msg: String) -> AsmError {
+        // Innermost site first, each one noting the site it came through.
+        let note = self.sites.iter().fold(None, |outer, s| {
+            Some(Box::new(AsmError {
+                file: s.file.clone(),
+                line: s.line,
+                col: s.col,
+                token: s.token.clone(),
+                msg: s.note.clone(),
+                source_line: s.source_line.clone(),
+                note: outer,
+            }))
+        });
+
+        AsmError {
+            file: self.file.to_string(),
+            line: self.number,
+            col: token.col,
+            token: token.text.to_string(),
+            msg,
+            source_line: self.text.to_string(),
+            note,
+        }
+    }
+
+    /// Makes `token` a site for the lines it brings in.
+    fn site(&self, token: &Token, note: String) -> Site {
+        Site {
+            file: self.file.to_string(),
+            line: self.number,
+            col: token.col,
+            token: token.text.to_string(),
+            source_line: self.text.to_string(),
+            note,
+        }
+    }
+
+    /// The line a debugger shows for this one: the outermost include or
+    /// macro call it came through, if any.
+    fn debug_line(&self) -> usize {
+        self.sites.first().map_or(self.number, |s| s.line)
+    }
+
+    fn label(&self) -> Option<&'a str> {
+        match self.tokens[..] {
+            [Token { text, .. }] => text.strip_suffix(':'),
+            _ => None,
+        }
+    }
+}
+
+/// A line kept by the assembler once includes and macros are expanded.
+#[derive(Clone)]
+struct Expanded {
+    file: String,
+    number: usize,
+    text: String,
+    sites: Vec<Site>,
+}
+
+impl Expanded {
+    fn line(&self) -> Line<'_> {
+        Line::new(&self.file, self.number, &self.text, &self.sites)
+    }
+}
+
+/// Splits a line into whitespace separated tokens, dropping `#` comments.
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let code = match text.split_once('#') {
+        Some((code, _comment)) => code,
+        None => text,
+    };
+
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, c) in code.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                tokens.push(Token {
+                    text: &code[s..i],
+                    col: code[..s].chars().count() + 1,
+                });
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+
+    if let Some(s) = start {
+        tokens.push(Token {
+            text: &code[s..],
+            col: code[..s].chars().count() + 1,
+        });
+    }
+
+    tokens
+}
+
+/// Where a program's instructions came from, for debuggers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugInfo {
+    /// Instruction index each label resolves to. Labels of imported files
+    /// are prefixed with the file's name, e.g. `math.square`.
+    pub labels: HashMap<String, usize>,
+    /// 1-based source line of every instruction, in the main file. Code
+    /// from an include, a macro or an import is put on the line that
+    /// brought it in.
+    pub lines: Vec<usize>,
+}
+
+impl DebugInfo {
+    /// Resolves a label or an instruction index to an index into a program
+    /// of `len` instructions.
+    pub fn location(&self, s: &str, len: usize) -> Result<usize, String> {
+        let ip = match s.parse::<usize>() {
+            Ok(ip) => ip,
+            Err(_) => match self.labels.get(s) {
+                Some(ip) => *ip,
+                None => return Err(format!("no label or instruction `{s}`")),
+            },
+        };
+
+        if ip < len {
+            Ok(ip)
+        } else {
+            Err(format!("no instruction at {ip}"))
+        }
+    }
+}
+
+/// Assembles `.vm` source text into a program.
+pub fn assemble(src: &str) -> Result<Vec<Ins>, Vec<AsmError>> {
+    assemble_named("<source>", src)
+}
+
+/// Like `assemble`, with `file` used to label diagnostics.
+pub fn assemble_named(file: &str, src: &str) -> Result<Vec<Ins>, Vec<AsmError>> {
+    assemble_with_debug_info(file, src).map(|(prog, _)| prog)
+}
+
+/// Like `assemble_named`, also returning the labels and source line of
+/// every instruction.
+///
+/// `.include "file"` pastes in another file, and `.import name... from
+/// "file"` assembles another file as a module of its own, appended after
+/// the main program, whose `.export`ed labels and constants become usable
+/// under those names. Both find files next to the file that names them.
+///
+/// Each file then has its macros expanded, see `expand_macros`. Assembly
+/// takes two passes over the result. The first gives every instruction its
+/// index, skipping labels, directives, comments and blank lines, and binds
+/// each label to the index of the instruction after it. Constants and
+/// imports are resolved next, then the second pass encodes the instructions
+/// against the finished tables, so a label can be used before it is
+/// defined.
+pub fn assemble_with_debug_info(
+    file: &str,
+    src: &str,
+) -> Result<(Vec<Ins>, DebugInfo), Vec<AsmError>> {
+    assemble_files(file, src, false).map(|a| (a.prog, a.info))
+}
+
+/// Assembles a single file into an object for `object::link`, leaving its
+/// imports for the linker to find among the other objects.
+///
+/// The files named by `.import`s are not read, and an imported name can
+/// only be the whole target of a `jump`, `jumpif` or `call`, which becomes
+/// a relocation. Only labels can be exported; they make up the object's
+/// symbols.
+pub fn assemble_object(file: &str, src: &str) -> Result<Object, Vec<AsmError>> {
+    assemble_files(file, src, true).map(|a| Object {
+        code: a.prog,
+        symbols: a.symbols,
+        relocations: a.relocations,
+    })
+}
+
+struct Assembled {
+    prog: Vec<Ins>,
+    info: DebugInfo,
+    /// Exported labels, for objects.
+    symbols: Vec<(String, usize)>,
+    relocations: Vec<(usize, String)>,
+}
+
+/// Assembles `file` and every file it imports or, for an `object`, just
+/// `file` with its imports left unresolved.
+fn assemble_files(file: &str, src: &str, object: bool) -> Result<Assembled, Vec<AsmError>> {
+    let mut errors = Vec::new();
+
+    let mut loader = Loader {
+        modules: Vec::new(),
+        order: Vec::new(),
+        loaded: HashMap::new(),
+        loading: Vec::new(),
+        follow_imports: !object,
+        errors: &mut errors,
+    };
+    loader.load(file, src, fs::canonicalize(file).ok(), None);
+    let Loader { modules, order, .. } = loader;
+
+    let lines = modules
+        .iter()
+        .map(|m| m.lines.iter().map(Expanded::line).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    // Pass 1: addresses, and which constants and imports to resolve once
+    // they are known.
+    let mut units = modules
+        .iter()
+        .zip(&lines)
+        .map(|(m, lines)| first_pass(lines, &m.imports, &mut errors))
+        .collect::<Vec<_>>();
+
+    let mut base = 0;
+    for u in units.iter_mut() {
+        u.base = base;
+        base += u.code.len();
+    }
+
+    // Imported files first, so their exports are ready for their importers.
+    let mut symbols = vec![HashMap::new(); units.len()];
+    for &m in &order {
+        symbols[m] = resolve(&units, &symbols, m, &mut errors);
+    }
+
+    // Pass 2: encoding.
+    let mut a = Assembled {
+        prog: Vec::new(),
+        info: DebugInfo::default(),
+        symbols: Vec::new(),
+        relocations: Vec::new(),
+    };
+
+    for (m, u) in units.iter().enumerate() {
+        for l in &u.code {
+            let external = match external(l, &u.externs) {
+                Ok(external) => external,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+            // Left at 0 for the linker.
+            let lookup = |n: &str| match Some(n) == external {
+                true => Some(Word::Int(0)),
+                false => symbols[m].get(n).copied(),
+            };
+
+            match parse_ins(l, &lookup) {
+                Ok(ins) => {
+                    if let Some(name) = external {
+                        a.relocations.push((a.prog.len(), name.to_string()));
+                    }
+                    a.prog.push(ins);
+                    a.info
+                        .lines
+                        .push(modules[m].imported_at.unwrap_or_else(|| l.debug_line()));
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        for (label, ip) in &u.labels {
+            let name = match modules[m].namespace.as_str() {
+                "" => label.to_string(),
+                ns => format!("{ns}.{label}"),
+            };
+            a.info.labels.insert(name, u.base + ip);
+        }
+    }
+
+    if object {
+        let u = &units[0];
+        for (l, name) in &u.exports {
+            match u.labels.iter().find(|(label, _)| *label == name.text) {
+                Some((label, ip)) => a.symbols.push((label.to_string(), *ip)),
+                None if symbols[0].contains_key(name.text) => {
+                    let msg = format!(
+                        "only labels can be exported from an object, `{}` is not one",
+                        name.text
+                    );
+                    errors.push(l.error(name, msg));
+                }
+                // Already reported by `resolve`.
+                None => {}
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(a)
+    } else {
+        // The main file's errors first, then each other file's in order.
+        errors.sort_by_key(|e| (e.file != file, e.file.clone(), e.line, e.col));
+        Err(errors)
+    }
+}
+
+/// The imported name `l` jumps to or calls, if it is one of `externs`,
+/// names left to the linker. Anywhere else they are an error.
+fn external<'a>(l: &Line<'a>, externs: &[&str]) -> Result<Option<&'a str>, AsmError> {
+    if let [op, target] = &l.tokens[..] {
+        if matches!(op.text, "jump" | "jumpif" | "call") && externs.contains(&target.text) {
+            return Ok(Some(target.text));
+        }
+    }
+
+    let used = |t: &&Token| {
+        t.text
+            .split(|c| !is_name_char(c))
+            .any(|w| externs.contains(&w))
+    };
+    match l.tokens[1..].iter().find(used) {
+        Some(t) => Err(l.error(
+            t,
+            format!(
+                "`{}` uses an import of an object, which can only be the whole target of a `jump`, `jumpif` or `call`",
+                t.text
+            ),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// A file assembled into the program.
+struct Module {
+    /// Prefix of its labels in `DebugInfo`, empty for the main file.
+    namespace: String,
+    lines: Vec<Expanded>,
+    /// Where debuggers show its instructions: the line of the main file
+    /// whose `.import` brought it in. `None` for the main file.
+    imported_at: Option<usize>,
+    /// The module each `.import` refers to, by its index in `lines`, or
+    /// `None` if it is left to the linker.
+    imports: HashMap<usize, Option<usize>>,
+}
+
+/// Loads the main file and every file it imports, directly or not.
+struct Loader<'e> {
+    modules: Vec<Module>,
+    /// Indices into `modules`, each after every module it imports.
+    order: Vec<usize>,
+    /// Index of every module read so far, by canonical path.
+    loaded: HashMap<PathBuf, usize>,
+    /// Files being loaded, outermost first, to catch import cycles.
+    loading: Vec<(PathBuf, String)>,
+    /// False when assembling an object, whose imports the linker resolves.
+    follow_imports: bool,
+    errors: &'e mut Vec<AsmError>,
+}
+
+impl Loader<'_> {
+    fn load(
+        &mut self,
+        file: &str,
+        src: &str,
+        key: Option<PathBuf>,
+        imported_at: Option<usize>,
+    ) -> usize {
+        let mut open = key.iter().map(|k| (k.clone(), file.to_string())).collect();
+        let mut included = Vec::new();
+        include(file, src, &[], &mut open, &mut included, self.errors);
+
+        let source = included.iter().map(Expanded::line).collect();
+        let lines = expand_macros(source, self.errors);
+
+        let index = self.modules.len();
+        let namespace = match index {
+            0 => String::new(),
+            _ => self.namespace(file),
+        };
+        let requests = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.line().tokens[0].text == ".import")
+            .map(|(i, e)| (i, e.clone()))
+            .collect::<Vec<_>>();
+        self.modules.push(Module {
+            namespace,
+            lines,
+            imported_at,
+            imports: HashMap::new(),
+        });
+
+        let on_disk = key.is_some();
+        if let Some(key) = key {
+            self.loaded.insert(key.clone(), index);
+            self.loading.push((key, file.to_string()));
+        }
+
+        let mut imports = HashMap::new();
+        for (i, e) in requests {
+            let l = e.line();
+            let path = match import_line(&l) {
+                Ok((_, path)) => path,
+                Err(e) => {
+                    self.errors.push(e);
+                    continue;
+                }
+            };
+
+            if !self.follow_imports {
+                imports.insert(i, None);
+                continue;
+            }
+            let at = imported_at.unwrap_or_else(|| l.debug_line());
+            if let Some(m) = self.import(&l, path, at) {
+                imports.insert(i, Some(m));
+            }
+        }
+
+        self.modules[index].imports = imports;
+        if on_disk {
+            self.loading.pop();
+        }
+        self.order.push(index);
+
+        index
+    }
+
+    fn import(&mut self, l: &Line, path: &Token, at: usize) -> Option<usize> {
+        let (key, file, src) = match read(l, path) {
+            Ok(read) => read,
+            Err(e) => {
+                self.errors.push(e);
+                return None;
+            }
+        };
+
+        if let Some(first) = self.loading.iter().position(|(k, _)| *k == key) {
+            let cycle = self.loading[first..]
+                .iter()
+                .map(|(_, f)| f.as_str())
+                .chain([file.as_str()])
+                .collect::<Vec<_>>();
+            let msg = format!("import cycle: {}", cycle.join(" -> "));
+            self.errors.push(l.error(path, msg));
+            return None;
+        }
+        if let Some(m) = self.loaded.get(&key) {
+            return Some(*m);
+        }
+
+        Some(self.load(&file, &src, Some(key), Some(at)))
+    }
+
+    /// The file's name, numbered if another module already has it.
+    fn namespace(&self, file: &str) -> String {
+        let stem = Path::new(file)
+            .file_stem()
+            .map_or("module".into(), |s| s.to_string_lossy());
+        let taken = |ns: &str| self.modules.iter().any(|m| m.namespace == ns);
+
+        (1..)
+            .map(|n| match n {
+                1 => stem.to_string(),
+                n => format!("{stem}{n}"),
+            })
+            .find(|ns| !taken(ns))
+            .unwrap()
+    }
+}
+
+/// Appends the lines of `src` to `out`, replacing every `.include "file"`
+/// with the lines of that file. `open` holds the files being included, to
+/// catch cycles.
+fn include(
+    file: &str,
+    src: &str,
+    sites: &[Site],
+    open: &mut Vec<(PathBuf, String)>,
+    out: &mut Vec<Expanded>,
+    errors: &mut Vec<AsmError>,
+) {
+    for (i, text) in src.lines().enumerate() {
+        let l = Line::new(file, i + 1, text, sites);
+        match l.tokens.first() {
+            None => continue,
+            Some(t) if t.text == ".include" => {}
+            Some(_) => {
+                out.push(Expanded {
+                    file: file.to_string(),
+                    number: l.number,
+                    text: text.to_string(),
+                    sites: sites.to_vec(),
+                });
+                continue;
+            }
+        }
+
+        let [_, path] = &l.tokens[..] else {
+            let msg = "`.include` expects a file name in quotes".to_string();
+            errors.push(l.error(l.tokens.get(2).unwrap_or(&l.tokens[0]), msg));
+            continue;
+        };
+        let (key, included, src) = match read(&l, path) {
+            Ok(read) => read,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+
+        if let Some(first) = open.iter().position(|(k, _)| *k == key) {
+            let cycle = open[first..]
+                .iter()
+                .map(|(_, f)| f.as_str())
+                .chain([included.as_str()])
+                .collect::<Vec<_>>();
+            errors.push(l.error(path, format!("include cycle: {}", cycle.join(" -> "))));
+            continue;
+        }
+
+        let mut inner = sites.to_vec();
+        inner.push(l.site(&l.tokens[0], "in the file included from here".to_string()));
+
+        open.push((key, included.clone()));
+        include(&included, &src, &inner, open, out, errors);
+        open.pop();
+    }
+}
+
+/// Reads the file named by the quoted `path` token, found next to `l`'s
+/// file. Returns its canonical path, its name for diagnostics and its text.
+fn read(l: &Line, path: &Token) -> Result<(PathBuf, String, String), AsmError> {
+    let Some(name) = path
+        .text
+        .strip_prefix('"')
+        .and_then(|p| p.strip_suffix('"'))
+    else {
+        let msg = format!("expected a file name in quotes, found `{}`", path.text);
+        return Err(l.error(path, msg));
+    };
+
+    let resolved = Path::new(l.file)
+        .parent()
+        .map_or_else(|| PathBuf::from(name), |dir| dir.join(name));
+    let file = resolved.display().to_string();
+
+    let read =
+        fs::canonicalize(&resolved).and_then(|key| Ok((key, fs::read_to_string(&resolved)?)));
+    match read {
+        Ok((key, src)) => Ok((key, file, src)),
+        Err(e) => Err(l.error(path, format!("unable to read `{file}`: {e}"))),
+    }
+}
+
+/// Splits `.import name... from "file"` into the names and the file.
+fn import_line<'l, 'a>(l: &'l Line<'a>) -> Result<(&'l [Token<'a>], &'l Token<'a>), AsmError> {
+    match &l.tokens[1..] {
+        [names @ .., from, path] if !names.is_empty() && from.text == "from" => Ok((names, path)),
+        _ => Err(l.error(
+            &l.tokens[0],
+            "`.import` expects `.import name... from \"file\"`".to_string(),
+        )),
+    }
+}
+
+/// What the first pass learns about a module.
+struct Unit<'a> {
+    /// Index of its first instruction in the program.
+    base: usize,
+    code: Vec<&'a Line<'a>>,
+    /// Index of each label, counting from `base`.
+    labels: Vec<(&'a str, usize)>,
+    consts: Vec<(&'a Line<'a>, &'a str)>,
+    /// Each imported name and the module it comes from.
+    imports: Vec<(&'a Line<'a>, &'a Token<'a>, usize)>,
+    exports: Vec<(&'a Line<'a>, &'a Token<'a>)>,
+    /// Imported names left to the linker.
+    externs: Vec<&'a str>,
+}
+
+fn first_pass<'a>(
+    lines: &'a [Line<'a>],
+    imports: &HashMap<usize, Option<usize>>,
+    errors: &mut Vec<AsmError>,
+) -> Unit<'a> {
+    let mut u = Unit {
+        base: 0,
+        code: Vec::new(),
+        labels: Vec::new(),
+        consts: Vec::new(),
+        imports: Vec::new(),
+        exports: Vec::new(),
+        externs: Vec::new(),
+    };
+    let mut defined_on = HashMap::new();
+
+    let mut define =
+        |errors: &mut Vec<AsmError>, l: &Line<'a>, kind: &str, name: &'a str, token: &Token| {
+            let Some((file, line)) = defined_on.get(name) else {
+                defined_on.insert(name, (l.file, l.number));
+                return true;
+            };
+
+            let at = match *file == l.file {
+                true => format!("on line {line}"),
+                false => format!("at {file}:{line}"),
+            };
+            errors.push(l.error(token, format!("{kind} `{name}` is already defined {at}")));
+            false
+        };
+
+    for (i, l) in lines.iter().enumerate() {
+        let Some(first) = l.tokens.first() else {
+            continue;
+        };
+
+        if let Some(label) = l.label() {
+            if !is_label(label) {
+                let msg = format!("`{label}` is not a valid label name");
+                errors.push(l.error(first, msg));
+            } else if define(errors, l, "label", label, first) {
+                u.labels.push((label, u.code.len()));
+            }
+            continue;
+        }
+
+        match first.text {
+            ".equ" | ".define" => match constant(l) {
+                Ok(name) => {
+                    if define(errors, l, "constant", name.text, name) {
+                        u.consts.push((l, name.text));
+                    }
+                }
+                Err(e) => errors.push(e),
+            },
+            ".export" => match &l.tokens[1..] {
+                [] => errors.push(l.error(first, "`.export` expects a name".to_string())),
+                names => u.exports.extend(names.iter().map(|t| (l, t))),
+            },
+            ".import" => {
+                // A bad import was reported when its file was loaded.
+                let (Ok((names, _)), Some(m)) = (import_line(l), imports.get(&i)) else {
+                    continue;
+                };
+                for t in names {
+                    if !define(errors, l, "import", t.text, t) {
+                        continue;
+                    }
+                    match m {
+                        Some(m) => u.imports.push((l, t, *m)),
+                        None => u.externs.push(t.text),
+                    }
+                }
+            }
+            d if d.starts_with('.') => {
+                errors.push(l.error(first, format!("unknown directive `{d}`")));
+            }
+            _ => u.code.push(l),
+        }
+    }
+
+    u
+}
+
+/// Builds module `m`'s symbol table: its labels, its imports from tables
+/// already built, then its constants in order, so a constant may use any
+/// label or import but only the constants defined above it.
+fn resolve<'a>(
+    units: &[Unit<'a>],
+    symbols: &[HashMap<&'a str, Word>],
+    m: usize,
+    errors: &mut Vec<AsmError>,
+) -> HashMap<&'a str, Word> {
+    let u = &units[m];
+    let mut table = HashMap::new();
+
+    for (label, ip) in &u.labels {
+        table.insert(*label, Word::Int((u.base + ip) as i64));
+    }
+
+    for (l, name, from) in &u.imports {
+        if !units[*from]
+            .exports
+            .iter()
+            .any(|(_, t)| t.text == name.text)
+        {
+            let file = import_line(l).map_or("", |(_, path)| path.text.trim_matches('"'));
+            let msg = format!("`{file}` does not export `{}`", name.text);
+            errors.push(l.error(name, msg));
+            continue;
+        }
+        if let Some(w) = symbols[*from].get(name.text) {
+            table.insert(name.text, *w);
+        }
+    }
+
+    for (l, name) in &u.consts {
+        let value = &l.tokens[2];
+        match expr::eval(value.text, |n| table.get(n).copied()) {
+            Ok(w) => _ = table.insert(name, w),
+            Err(e) => errors.push(l.error(value, e.to_string())),
+        }
+    }
+
+    for (l, name) in &u.exports {
+        if !table.contains_key(name.text) {
+            let msg = format!("cannot export `{}`, it is not defined", name.text);
+            errors.push(l.error(name, msg));
+        }
+    }
+
+    table
+}
+
+/// Checks a `.equ NAME value` or `.define NAME value` line, returning the
+/// name. The two directives are the same; the value is a constant
+/// expression, see `expr::eval`.
+fn constant<'l, 'a>(l: &'l Line<'a>) -> Result<&'l Token<'a>, AsmError> {
+    let d = &l.tokens[0];
+
+    let [_, name, _, ..] = &l.tokens[..] else {
+        return Err(l.error(d, format!("`{}` expects a name and a value", d.text)));
+    };
+    if let Some(extra) = l.tokens.get(3) {
+        return Err(l.error(
+            extra,
+            format!("unexpected operand `{}` for `{}`", extra.text, d.text),
+        ));
+    }
+
+    if !is_name(name.text) {
+        return Err(l.error(
+            name,
+            format!("`{}` is not a valid constant name", name.text),
+        ));
+    }
+
+    Ok(name)
+}
+
+/// Whether `s` can name a constant, macro or macro parameter: an
+/// identifier that is not also a literal or an instruction.
+fn is_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && s.chars().all(is_name_char)
+        && Word::try_from(s).is_err()
+        && arity(s).is_none()
+}
+
+/// Whether `s` can name a label: like `is_name`, but also with `.`s, as in
+/// the labels of macro expansions, since operands read names with them.
+fn is_label(s: &str) -> bool {
+    s.split('.')
+        .all(|part| !part.is_empty() && part.chars().all(is_name_char))
+        && is_name(s.split('.').next().unwrap_or_default())
+        && Word::try_from(s).is_err()
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// How deeply macro calls may nest, which stops runaway recursion.
+const MAX_MACRO_DEPTH: usize = 64;
+
+struct Macro<'a> {
+    file: &'a str,
+    line: usize,
+    params: Vec<&'a str>,
+    body: Vec<Line<'a>>,
+    /// Labels defined in the body, renamed for every expansion so each one
+    /// gets its own.
+    labels: Vec<&'a str>,
+}
+
+/// Takes out `.macro name params... .endm` definitions and replaces every
+/// call to one, `name args...`, with its body.
+///
+/// Within the body each parameter is replaced by its argument wherever it
+/// appears as a whole word, including inside expressions such as `dup n-1`.
+/// Lines of an expansion keep the body's file and line numbers, and
+/// remember the calls they came from for diagnostics.
+fn expand_macros(lines: Vec<Line<'_>>, errors: &mut Vec<AsmError>) -> Vec<Expanded> {
+    let mut macros = HashMap::new();
+    let mut top = Vec::new();
+    let mut lines = lines.into_iter();
+
+    while let Some(l) = lines.next() {
+        match l.tokens[0].text {
+            ".macro" => {
+                let mut body = Vec::new();
+                let mut closed = false;
+                for b in lines.by_ref() {
+                    match b.tokens[0].text {
+                        ".endm" => {
+                            closed = true;
+                            break;
+                        }
+                        ".macro" => errors.push(b.error(
+                            &b.tokens[0],
+                            "a macro cannot be defined inside another".to_string(),
+                        )),
+                        _ if is_reserved(&b, errors) => {}
+                        _ => body.push(b),
+                    }
+                }
+
+                if !closed {
+                    errors.push(
+                        l.error(&l.tokens[0], "`.macro` has no matching `.endm`".to_string()),
+                    );
+                }
+                match define_macro(&l, body, &macros) {
+                    Ok((name, m)) => _ = macros.insert(name, m),
+                    Err(e) => errors.push(e),
+                }
+            }
+            ".endm" => errors.push(l.error(&l.tokens[0], "`.endm` without a `.macro`".to_string())),
+            _ if is_reserved(&l, errors) => {}
+            _ => top.push(l),
+        }
+    }
+
+    let mut x = Expander {
+        macros: &macros,
+        expansions: 0,
+        out: Vec::new(),
+        errors,
+    };
+    for l in top {
+        let e = Expanded {
+            file: l.file.to_string(),
+            number: l.number,
+            text: l.text.to_string(),
+            sites: l.sites.to_vec(),
+        };
+        x.line(e, 0);
+    }
+
+    x.out
+}
+
+/// Reports a label written the way expansions rename theirs,
+/// `label.macro.n`, which could otherwise clash with one of them.
+fn is_reserved(l: &Line<'_>, errors: &mut Vec<AsmError>) -> bool {
+    let Some(label) = l.label() else {
+        return false;
+    };
+    let parts = label.split('.').collect::<Vec<_>>();
+    if parts.len() < 3 || !parts[parts.len() - 1].bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+
+    let msg = format!(
+        "`{label}` is not a valid label name, macro expansions name their labels `label.macro.n`"
+    );
+    errors.push(l.error(&l.tokens[0], msg));
+    true
+}
+
+fn define_macro<'a>(
+    l: &Line<'a>,
+    body: Vec<Line<'a>>,
+    macros: &HashMap<&str, Macro>,
+) -> Result<(&'a str, Macro<'a>), AsmError> {
+    let Some(name) = l.tokens.get(1) else {
+        return Err(l.error(&l.tokens[0], "`.macro` expects a name".to_string()));
+    };
+    if !is_name(name.text) {
+        let msg = format!("`{}` is not a valid macro name", name.text);
+        return Err(l.error(name, msg));
+    }
+    if let Some(m) = macros.get(name.text) {
+        let at = match m.file == l.file {
+            true => format!("on line {}", m.line),
+            false => format!("at {}:{}", m.file, m.line),
+        };
+        let msg = format!("macro `{}` is already defined {at}", name.text);
+        return Err(l.error(name, msg));
+    }
+
+    let mut params = Vec::new();
+    for p in &l.tokens[2..] {
+        if !is_name(p.text) {
+            let msg = format!("`{}` is not a valid parameter name", p.text);
+            return Err(l.error(p, msg));
+        }
+        if params.contains(&p.text) {
+            let msg = format!("parameter `{}` is repeated", p.text);
+            return Err(l.error(p, msg));
+        }
+        params.push(p.text);
+    }
+
+    let labels = body.iter().filter_map(|b| b.label()).collect();
+
+    Ok((
+        name.text,
+        Macro {
+            file: l.file,
+            line: l.number,
+            params,
+            body,
+            labels,
+        },
+    ))
+}
+
+struct Expander<'a, 'e> {
+    macros: &'a HashMap<&'a str, Macro<'a>>,
+    /// Calls expanded so far, numbering each expansion's labels.
+    expansions: usize,
+    out: Vec<Expanded>,
+    errors: &'e mut Vec<AsmError>,
+}
+
+impl Expander<'_, '_> {
+    /// Keeps `e`, or expands it if it calls a macro. `depth` counts the
+    /// macro calls it came from.
+    fn line(&mut self, e: Expanded, depth: usize) {
+        let l = e.line();
+        let Some(m) = l.tokens.first().and_then(|t| self.macros.get(t.text)) else {
+            self.out.push(e);
+            return;
+        };
+
+        let name = &l.tokens[0];
+        let args = &l.tokens[1..];
+
+        if depth >= MAX_MACRO_DEPTH {
+            let msg = format!(
+                "macro `{}` nests more than {MAX_MACRO_DEPTH} calls deep",
+                name.text
+            );
+            // Only the calls at either end say anything useful.
+            let ends = [e.sites[0].clone(), e.sites[e.sites.len() - 1].clone()];
+            let at = Line::new(&e.file, e.number, &e.text, &ends);
+            self.errors.push(at.error(&at.tokens[0], msg));
+            return;
+        }
+        if args.len() != m.params.len() {
+            let msg = format!(
+                "macro `{}` expects {} argument(s), found {}",
+                name.text,
+                m.params.len(),
+                args.len()
+            );
+            self.errors.push(l.error(name, msg));
+            return;
+        }
+
+        self.expansions += 1;
+        let mut subst = m
+            .labels
+            .iter()
+            .map(|label| (*label, format!("{label}.{}.{}", name.text, self.expansions)))
+            .collect::<HashMap<_, _>>();
+        for (p, arg) in m.params.iter().zip(args) {
+            subst.insert(p, arg.text.to_string());
+        }
+
+        let mut sites = e.sites.clone();
+        sites.push(l.site(name, format!("in this expansion of macro `{}`", name.text)));
+
+        let body = m
+            .body
+            .iter()
+            .map(|b| Expanded {
+                file: b.file.to_string(),
+                number: b.number,
+                text: substitute(b.text, &subst),
+                sites: sites.clone(),
+            })
+            .collect::<Vec<_>>();
+        for b in body {
+            self.line(b, depth + 1);
+        }
+    }
+}
+
+/// Replaces every whole word of `text` found in `subst`, leaving comments
+/// alone.
+fn substitute(text: &str, subst: &HashMap<&str, String>) -> String {
+    let (code, comment) = text.split_at(text.find('#').unwrap_or(text.len()));
+
+    let mut out = String::new();
+    let mut rest = code;
+    while let Some(start) = rest.find(is_name_char) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        let word = &rest[..end];
+        out.push_str(subst.get(word).map_or(word, String::as_str));
+        rest = &rest[end..];
+    }
+
+    out.push_str(rest);
+    out.push_str(comment);
+    out
+}
+
+pub fn read_source_file(sf: &str) -> Result<Vec<Ins>, Vec<AsmError>> {
+    let vm_file = fs::read_to_string(sf).map_err(|e| {
+        vec![AsmError {
+            file: sf.to_string(),
+            line: 0,
+            col: 0,
+            token: String::new(),
+            msg: format!("Unable to read file: {e}"),
+            source_line: String::new(),
+            note: None,
+        }]
+    })?;
+
+    assemble_named(sf, &vm_file)
+}
+
+/// Least and most operands a mnemonic takes, `None` if it is not an
+/// instruction.
+fn arity(mnemonic: &str) -> Option<(usize, usize)> {
+    match mnemonic {
+        "push" | "swap" | "dup" | "jump" | "jumpif" | "call" => Some((1, 1)),
+        "enter" | "load_local" | "store_local" | "load_arg" | "store_arg" => Some((1, 1)),
+
+        "pop" | "addi" | "subi" | "muli" | "divi" | "addf" | "subf" | "mulf" | "divf" | "ret"
+        | "leave" | "halt" | "noop" => Some((0, 0)),
+
+        "eqi" | "nei" | "lti" | "lei" | "gti" | "gei" | "eqf" | "nef" | "ltf" | "lef" | "gtf"
+        | "gef" | "and" | "or" | "xor" | "not" => Some((0, 0)),
+
+        "modi" | "remi" | "negi" | "negf" | "band" | "bor" | "bxor" | "bnot" | "shl" | "shr"
+        | "ushr" => Some((0, 0)),
+
+        "i2f" | "b2i" | "i2b" => Some((0, 0)),
+        "f2i" => Some((0, 1)),
+
+        _ => None,
+    }
+}
+
+fn parse_ins(l: &Line, lookup: &dyn Fn(&str) -> Option<Word>) -> Result<Ins, AsmError> {
+    let op = &l.tokens[0];
+    let operands = &l.tokens[1..];
+
+    let Some((min, max)) = arity(op.text) else {
+        return Err(l.error(op, format!("unknown instruction `{}`", op.text)));
+    };
+
+    if operands.len() < min {
+        return Err(l.error(op, format!("`{}` expects an operand", op.text)));
+    }
+    if let Some(extra) = operands.get(max) {
+        return Err(l.error(
+            extra,
+            format!("unexpected operand `{}` for `{}`", extra.text, op.text),
+        ));
+    }
+
+    // Plain literals are taken as they are, so e.g. `i64::MIN` still works.
+    let value = |v: &Token, what: &str| {
+        Word::try_from(v.text).or_else(|_| {
+            expr::eval(v.text, lookup).map_err(|e| {
+                let msg = match e {
+                    ExprErr::Undefined(name) => {
+                        format!("undefined {what} `{name}` for `{}`", op.text)
+                    }
+                    ExprErr::Invalid(msg) => msg,
+                };
+                l.error(v, msg)
+            })
+        })
+    };
+
+    let index = |v: &Token, what: &str| match value(v, what)? {
+        Word::Int(i) if i >= 0 => Ok(i as usize),
+        w => Err(l.error(
+            v,
+            format!("`{}` expects an index, found `{}` = {w}", op.text, v.text),
+        )),
+    };
+
+    match op.text {
+        "push" => value(&operands[0], "constant").map(Ins::Push),
+
+        "swap" => index(&operands[0], "constant").map(Ins::Swap),
+        "dup" => index(&operands[0], "constant").map(Ins::Dup),
+
+        "jump" => index(&operands[0], "label").map(Ins::Jump),
+        "jumpif" => index(&operands[0], "label").map(Ins::JumpIf),
+        "call" => index(&operands[0], "label").map(Ins::Call),
+        "ret" => Ok(Ins::Ret),
+
+        "enter" => index(&operands[0], "constant").map(Ins::Enter),
+        "leave" => Ok(Ins::Leave),
+        "load_local" => index(&operands[0], "constant").map(Ins::LoadLocal),
+        "store_local" => index(&operands[0], "constant").map(Ins::StoreLocal),
+        "load_arg" => index(&operands[0], "constant").map(Ins::LoadArg),
+        "store_arg" => index(&operands[0], "constant").map(Ins::StoreArg),
+
+        "pop" => Ok(Ins::Pop),
+
+        "eqi" => Ok(Ins::EqI),
+        "nei" => Ok(Ins::NeI),
+        "lti" => Ok(Ins::LtI),
+        "lei" => Ok(Ins::LeI),
+        "gti" => Ok(Ins::GtI),
+        "gei" => Ok(Ins::GeI),
+
+        "eqf" => Ok(Ins::EqF),
+        "nef" => Ok(Ins::NeF),
+        "ltf" => Ok(Ins::LtF),
+        "lef" => Ok(Ins::LeF),
+        "gtf" => Ok(Ins::GtF),
+        "gef" => Ok(Ins::GeF),
+
+        "and" => Ok(Ins::And),
+        "or" => Ok(Ins::Or),
+        "xor" => Ok(Ins::Xor),
+        "not" => Ok(Ins::Not),
+
+        "addi" => Ok(Ins::AddI),
+        "subi" => Ok(Ins::SubI),
+        "muli" => Ok(Ins::MulI),
+        "divi" => Ok(Ins::DivI),
+        "modi" => Ok(Ins::ModI),
+        "remi" => Ok(Ins::RemI),
+        "negi" => Ok(Ins::NegI),
+
+        "addf" => Ok(Ins::AddF),
+        "subf" => Ok(Ins::SubF),
+        "mulf" => Ok(Ins::MulF),
+        "divf" => Ok(Ins::DivF),
+        "negf" => Ok(Ins::NegF),
+
+        "band" => Ok(Ins::BAnd),
+        "bor" => Ok(Ins::BOr),
+        "bxor" => Ok(Ins::BXor),
+        "bnot" => Ok(Ins::BNot),
+        "shl" => Ok(Ins::Shl),
+        "shr" => Ok(Ins::Shr),
+        "ushr" => Ok(Ins::UShr),
+
+        "i2f" => Ok(Ins::I2F),
+        "f2i" => match operands.first() {
+            None => Ok(Ins::F2I(Rounding::Trunc)),
+            Some(v) => Rounding::try_from(v.text)
+                .map(Ins::F2I)
+                .map_err(|e| l.error(v, e)),
+        },
+        "b2i" => Ok(Ins::B2I),
+        "i2b" => Ok(Ins::I2B),
+
+        "halt" => Ok(Ins::Halt),
+        "noop" => Ok(Ins::NoOp),
+
+        _ => unreachable!("`{}` has an arity but no encoding", op.text),
+    }
+}
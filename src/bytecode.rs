@@ -0,0 +1,517 @@
+// Binary encoding of a program.
+//
+//     magic    b"RVMB"
+//     version  u16 LE
+//     count    u32 LE            number of instructions
+//     code     opcode u8 followed by its operand, if any
+//     checksum u32 LE            FNV-1a over everything before it
+//
+// Operands are little-endian: `u32` for jump/call targets, `dup`/`swap`
+// depths and frame slots, `i64`/`f64` for pushed words and a single byte
+// for booleans and `f2i` rounding modes.
+
+use crate::ins::{Ins, Rounding};
+use crate::word::Word;
+use std::fmt;
+use std::fs;
+use std::io;
+
+pub const MAGIC: [u8; 4] = *b"RVMB";
+// 2: comparisons take `a op b` with `b` on top, `gef` used to be reversed.
+pub const VERSION: u16 = 2;
+
+const HEADER_LEN: usize = 4 + 2 + 4;
+const CHECKSUM_LEN: usize = 4;
+
+const OP_NOOP: u8 = 0x00;
+const OP_PUSH_INT: u8 = 0x01;
+const OP_PUSH_FLOAT: u8 = 0x02;
+const OP_PUSH_BOOL: u8 = 0x03;
+const OP_POP: u8 = 0x04;
+
+const OP_ADDI: u8 = 0x10;
+const OP_SUBI: u8 = 0x11;
+const OP_MULI: u8 = 0x12;
+const OP_DIVI: u8 = 0x13;
+const OP_MODI: u8 = 0x14;
+const OP_REMI: u8 = 0x15;
+const OP_NEGI: u8 = 0x16;
+
+const OP_ADDF: u8 = 0x18;
+const OP_SUBF: u8 = 0x19;
+const OP_MULF: u8 = 0x1a;
+const OP_DIVF: u8 = 0x1b;
+const OP_NEGF: u8 = 0x1c;
+
+const OP_EQI: u8 = 0x20;
+const OP_NEI: u8 = 0x21;
+const OP_LTI: u8 = 0x22;
+const OP_LEI: u8 = 0x23;
+const OP_GTI: u8 = 0x24;
+const OP_GEI: u8 = 0x25;
+
+const OP_EQF: u8 = 0x28;
+const OP_NEF: u8 = 0x29;
+const OP_LTF: u8 = 0x2a;
+const OP_LEF: u8 = 0x2b;
+const OP_GTF: u8 = 0x2c;
+const OP_GEF: u8 = 0x2d;
+
+const OP_JUMP: u8 = 0x30;
+const OP_JUMPIF: u8 = 0x31;
+const OP_DUP: u8 = 0x32;
+const OP_SWAP: u8 = 0x33;
+const OP_CALL: u8 = 0x34;
+const OP_RET: u8 = 0x35;
+
+const OP_ENTER: u8 = 0x40;
+const OP_LEAVE: u8 = 0x41;
+const OP_LOAD_LOCAL: u8 = 0x42;
+const OP_STORE_LOCAL: u8 = 0x43;
+const OP_LOAD_ARG: u8 = 0x44;
+const OP_STORE_ARG: u8 = 0x45;
+
+const OP_AND: u8 = 0x50;
+const OP_OR: u8 = 0x51;
+const OP_XOR: u8 = 0x52;
+const OP_NOT: u8 = 0x53;
+
+const OP_BAND: u8 = 0x60;
+const OP_BOR: u8 = 0x61;
+const OP_BXOR: u8 = 0x62;
+const OP_BNOT: u8 = 0x63;
+const OP_SHL: u8 = 0x64;
+const OP_SHR: u8 = 0x65;
+const OP_USHR: u8 = 0x66;
+
+const OP_I2F: u8 = 0x70;
+const OP_F2I: u8 = 0x71;
+const OP_B2I: u8 = 0x72;
+const OP_I2B: u8 = 0x73;
+
+const OP_HALT: u8 = 0xff;
+
+#[derive(Debug)]
+pub enum BytecodeErr {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    TrailingBytes(usize),
+    BadOpcode { offset: usize, opcode: u8 },
+    BadOperand { offset: usize },
+    ChecksumMismatch { expected: u32, found: u32 },
+    OperandTooLarge(Ins),
+    ProgramTooLarge(usize),
+}
+
+impl fmt::Display for BytecodeErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeErr::Io(e) => write!(f, "Error: {e}"),
+            BytecodeErr::BadMagic => write!(f, "Error: Not an rvm bytecode file"),
+            BytecodeErr::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "Error: Unsupported bytecode version {v}, expected {VERSION}"
+                )
+            }
+            BytecodeErr::Truncated => write!(f, "Error: Bytecode is truncated"),
+            BytecodeErr::TrailingBytes(n) => {
+                write!(f, "Error: {n} unexpected bytes after the last instruction")
+            }
+            BytecodeErr::BadOpcode { offset, opcode } => {
+                write!(f, "Error: Unknown opcode {opcode:#04x} at offset {offset}")
+            }
+            BytecodeErr::BadOperand { offset } => {
+                write!(
+                    f,
+                    "Error: Invalid operand for instruction at offset {offset}"
+                )
+            }
+            BytecodeErr::ChecksumMismatch { expected, found } => write!(
+                f,
+                "Error: Checksum mismatch, expected {expected:#010x} found {found:#010x}"
+            ),
+            BytecodeErr::OperandTooLarge(ins) => {
+                write!(f, "Error: Operand of `{ins}` does not fit in 32 bits")
+            }
+            BytecodeErr::ProgramTooLarge(n) => {
+                write!(
+                    f,
+                    "Error: Program of {n} instructions is too large to encode"
+                )
+            }
+        }
+    }
+}
+
+impl From<io::Error> for BytecodeErr {
+    fn from(e: io::Error) -> Self {
+        BytecodeErr::Io(e)
+    }
+}
+
+/// True if `bytes` start with the bytecode magic header.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+pub fn encode(program: &[Ins]) -> Result<Vec<u8>, BytecodeErr> {
+    let count =
+        u32::try_from(program.len()).map_err(|_| BytecodeErr::ProgramTooLarge(program.len()))?;
+
+    let mut out = Vec::with_capacity(HEADER_LEN + program.len() * 2 + CHECKSUM_LEN);
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&count.to_le_bytes());
+
+    for ins in program {
+        encode_ins(*ins, &mut out)?;
+    }
+
+    let checksum = fnv1a(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+
+    Ok(out)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<Ins>, BytecodeErr> {
+    let mut r = open(bytes, MAGIC)?;
+
+    let version = u16::from_le_bytes(r.take()?);
+    if version != VERSION {
+        return Err(BytecodeErr::UnsupportedVersion(version));
+    }
+
+    let count = r.u32()?;
+    let mut program = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        program.push(decode_ins(&mut r)?);
+    }
+
+    match r.remaining() {
+        0 => Ok(program),
+        n => Err(BytecodeErr::TrailingBytes(n)),
+    }
+}
+
+pub fn write_file(path: &str, program: &[Ins]) -> Result<usize, BytecodeErr> {
+    let bytes = encode(program)?;
+    fs::write(path, &bytes)?;
+
+    Ok(bytes.len())
+}
+
+pub fn read_file(path: &str) -> Result<Vec<Ins>, BytecodeErr> {
+    decode(&fs::read(path)?)
+}
+
+fn encode_ins(ins: Ins, out: &mut Vec<u8>) -> Result<(), BytecodeErr> {
+    let operand = |v: usize| u32::try_from(v).map_err(|_| BytecodeErr::OperandTooLarge(ins));
+
+    match ins {
+        Ins::NoOp => out.push(OP_NOOP),
+        Ins::Push(Word::Int(v)) => {
+            out.push(OP_PUSH_INT);
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        Ins::Push(Word::Float(v)) => {
+            out.push(OP_PUSH_FLOAT);
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        Ins::Push(Word::Boolean(v)) => {
+            out.push(OP_PUSH_BOOL);
+            out.push(v as u8);
+        }
+        Ins::Pop => out.push(OP_POP),
+
+        Ins::AddI => out.push(OP_ADDI),
+        Ins::SubI => out.push(OP_SUBI),
+        Ins::MulI => out.push(OP_MULI),
+        Ins::DivI => out.push(OP_DIVI),
+        Ins::ModI => out.push(OP_MODI),
+        Ins::RemI => out.push(OP_REMI),
+        Ins::NegI => out.push(OP_NEGI),
+
+        Ins::AddF => out.push(OP_ADDF),
+        Ins::SubF => out.push(OP_SUBF),
+        Ins::MulF => out.push(OP_MULF),
+        Ins::DivF => out.push(OP_DIVF),
+        Ins::NegF => out.push(OP_NEGF),
+
+        Ins::BAnd => out.push(OP_BAND),
+        Ins::BOr => out.push(OP_BOR),
+        Ins::BXor => out.push(OP_BXOR),
+        Ins::BNot => out.push(OP_BNOT),
+        Ins::Shl => out.push(OP_SHL),
+        Ins::Shr => out.push(OP_SHR),
+        Ins::UShr => out.push(OP_USHR),
+
+        Ins::I2F => out.push(OP_I2F),
+        Ins::F2I(v) => {
+            out.push(OP_F2I);
+            out.push(match v {
+                Rounding::Trunc => 0,
+                Rounding::Round => 1,
+                Rounding::Floor => 2,
+                Rounding::Ceil => 3,
+            });
+        }
+        Ins::B2I => out.push(OP_B2I),
+        Ins::I2B => out.push(OP_I2B),
+
+        Ins::EqI => out.push(OP_EQI),
+        Ins::NeI => out.push(OP_NEI),
+        Ins::LtI => out.push(OP_LTI),
+        Ins::LeI => out.push(OP_LEI),
+        Ins::GtI => out.push(OP_GTI),
+        Ins::GeI => out.push(OP_GEI),
+
+        Ins::EqF => out.push(OP_EQF),
+        Ins::NeF => out.push(OP_NEF),
+        Ins::LtF => out.push(OP_LTF),
+        Ins::LeF => out.push(OP_LEF),
+        Ins::GtF => out.push(OP_GTF),
+        Ins::GeF => out.push(OP_GEF),
+
+        Ins::And => out.push(OP_AND),
+        Ins::Or => out.push(OP_OR),
+        Ins::Xor => out.push(OP_XOR),
+        Ins::Not => out.push(OP_NOT),
+
+        Ins::Jump(v) => {
+            out.push(OP_JUMP);
+            out.extend_from_slice(&operand(v)?.to_le_bytes());
+        }
+        Ins::JumpIf(v) => {
+            out.push(OP_JUMPIF);
+            out.extend_from_slice(&operand(v)?.to_le_bytes());
+        }
+        Ins::Call(v) => {
+            out.push(OP_CALL);
+            out.extend_from_slice(&operand(v)?.to_le_bytes());
+        }
+        Ins::Ret => out.push(OP_RET),
+        Ins::Dup(v) => {
+            out.push(OP_DUP);
+            out.extend_from_slice(&operand(v)?.to_le_bytes());
+        }
+        Ins::Swap(v) => {
+            out.push(OP_SWAP);
+            out.extend_from_slice(&operand(v)?.to_le_bytes());
+        }
+
+        Ins::Enter(v) => {
+            out.push(OP_ENTER);
+            out.extend_from_slice(&operand(v)?.to_le_bytes());
+        }
+        Ins::Leave => out.push(OP_LEAVE),
+        Ins::LoadLocal(v) => {
+            out.push(OP_LOAD_LOCAL);
+            out.extend_from_slice(&operand(v)?.to_le_bytes());
+        }
+        Ins::StoreLocal(v) => {
+            out.push(OP_STORE_LOCAL);
+            out.extend_from_slice(&operand(v)?.to_le_bytes());
+        }
+        Ins::LoadArg(v) => {
+            out.push(OP_LOAD_ARG);
+            out.extend_from_slice(&operand(v)?.to_le_bytes());
+        }
+        Ins::StoreArg(v) => {
+            out.push(OP_STORE_ARG);
+            out.extend_from_slice(&operand(v)?.to_le_bytes());
+        }
+
+        Ins::Halt => out.push(OP_HALT),
+    }
+
+    Ok(())
+}
+
+fn decode_ins(r: &mut Reader) -> Result<Ins, BytecodeErr> {
+    let offset = r.pos();
+    let [opcode] = r.take()?;
+
+    let ins = match opcode {
+        OP_NOOP => Ins::NoOp,
+        OP_PUSH_INT => Ins::Push(Word::Int(i64::from_le_bytes(r.take()?))),
+        OP_PUSH_FLOAT => Ins::Push(Word::Float(f64::from_le_bytes(r.take()?))),
+        OP_PUSH_BOOL => match r.take()? {
+            [0] => Ins::Push(Word::Boolean(false)),
+            [1] => Ins::Push(Word::Boolean(true)),
+            _ => return Err(BytecodeErr::BadOperand { offset }),
+        },
+        OP_POP => Ins::Pop,
+
+        OP_ADDI => Ins::AddI,
+        OP_SUBI => Ins::SubI,
+        OP_MULI => Ins::MulI,
+        OP_DIVI => Ins::DivI,
+        OP_MODI => Ins::ModI,
+        OP_REMI => Ins::RemI,
+        OP_NEGI => Ins::NegI,
+
+        OP_ADDF => Ins::AddF,
+        OP_SUBF => Ins::SubF,
+        OP_MULF => Ins::MulF,
+        OP_DIVF => Ins::DivF,
+        OP_NEGF => Ins::NegF,
+
+        OP_BAND => Ins::BAnd,
+        OP_BOR => Ins::BOr,
+        OP_BXOR => Ins::BXor,
+        OP_BNOT => Ins::BNot,
+        OP_SHL => Ins::Shl,
+        OP_SHR => Ins::Shr,
+        OP_USHR => Ins::UShr,
+
+        OP_I2F => Ins::I2F,
+        OP_F2I => match r.take()? {
+            [0] => Ins::F2I(Rounding::Trunc),
+            [1] => Ins::F2I(Rounding::Round),
+            [2] => Ins::F2I(Rounding::Floor),
+            [3] => Ins::F2I(Rounding::Ceil),
+            _ => return Err(BytecodeErr::BadOperand { offset }),
+        },
+        OP_B2I => Ins::B2I,
+        OP_I2B => Ins::I2B,
+
+        OP_EQI => Ins::EqI,
+        OP_NEI => Ins::NeI,
+        OP_LTI => Ins::LtI,
+        OP_LEI => Ins::LeI,
+        OP_GTI => Ins::GtI,
+        OP_GEI => Ins::GeI,
+
+        OP_EQF => Ins::EqF,
+        OP_NEF => Ins::NeF,
+        OP_LTF => Ins::LtF,
+        OP_LEF => Ins::LeF,
+        OP_GTF => Ins::GtF,
+        OP_GEF => Ins::GeF,
+
+        OP_AND => Ins::And,
+        OP_OR => Ins::Or,
+        OP_XOR => Ins::Xor,
+        OP_NOT => Ins::Not,
+
+        OP_JUMP => Ins::Jump(r.u32()?),
+        OP_JUMPIF => Ins::JumpIf(r.u32()?),
+        OP_CALL => Ins::Call(r.u32()?),
+        OP_RET => Ins::Ret,
+        OP_DUP => Ins::Dup(r.u32()?),
+        OP_SWAP => Ins::Swap(r.u32()?),
+
+        OP_ENTER => Ins::Enter(r.u32()?),
+        OP_LEAVE => Ins::Leave,
+        OP_LOAD_LOCAL => Ins::LoadLocal(r.u32()?),
+        OP_STORE_LOCAL => Ins::StoreLocal(r.u32()?),
+        OP_LOAD_ARG => Ins::LoadArg(r.u32()?),
+        OP_STORE_ARG => Ins::StoreArg(r.u32()?),
+
+        OP_HALT => Ins::Halt,
+
+        _ => return Err(BytecodeErr::BadOpcode { offset, opcode }),
+    };
+
+    Ok(ins)
+}
+
+/// Why a file in one of the crate's binary formats was rejected before any
+/// of its fields were read.
+pub(crate) enum Framing {
+    BadMagic,
+    Truncated,
+    ChecksumMismatch { expected: u32, found: u32 },
+}
+
+impl From<Framing> for BytecodeErr {
+    fn from(e: Framing) -> Self {
+        match e {
+            Framing::BadMagic => BytecodeErr::BadMagic,
+            Framing::Truncated => BytecodeErr::Truncated,
+            Framing::ChecksumMismatch { expected, found } => {
+                BytecodeErr::ChecksumMismatch { expected, found }
+            }
+        }
+    }
+}
+
+/// A field ran past the end of the input.
+pub(crate) struct Truncated;
+
+impl From<Truncated> for BytecodeErr {
+    fn from(_: Truncated) -> Self {
+        BytecodeErr::Truncated
+    }
+}
+
+/// Checks that `bytes` start with `magic`, leave room for a version, and
+/// end with the FNV-1a checksum of everything before it. Returns a reader
+/// over the body, at the version just after the magic.
+///
+/// Bytecode, snapshots and objects all share this layout.
+pub(crate) fn open(bytes: &[u8], magic: [u8; 4]) -> Result<Reader<'_>, Framing> {
+    if !bytes.starts_with(&magic) {
+        return Err(Framing::BadMagic);
+    }
+    if bytes.len() < magic.len() + 2 + CHECKSUM_LEN {
+        return Err(Framing::Truncated);
+    }
+
+    let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    let expected = u32::from_le_bytes(checksum.try_into().unwrap());
+    let found = fnv1a(body);
+    if expected != found {
+        return Err(Framing::ChecksumMismatch { expected, found });
+    }
+
+    Ok(Reader {
+        bytes: body,
+        pos: magic.len(),
+    })
+}
+
+/// Reads little-endian fields one after another, failing with `Truncated`
+/// rather than reading past the end.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Offset of the next field, for errors.
+    pub(crate) fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Bytes left unread.
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    pub(crate) fn slice(&mut self, n: usize) -> Result<&'a [u8], Truncated> {
+        let end = self.pos.checked_add(n).ok_or(Truncated)?;
+        let chunk = self.bytes.get(self.pos..end).ok_or(Truncated)?;
+        self.pos = end;
+
+        Ok(chunk)
+    }
+
+    pub(crate) fn take<const N: usize>(&mut self) -> Result<[u8; N], Truncated> {
+        Ok(self.slice(N)?.try_into().unwrap())
+    }
+
+    /// A `u32` LE, such as a count, length or instruction index.
+    pub(crate) fn u32(&mut self) -> Result<usize, Truncated> {
+        Ok(u32::from_le_bytes(self.take()?) as usize)
+    }
+}
+
+pub(crate) fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, b| {
+        (hash ^ *b as u32).wrapping_mul(0x0100_0193)
+    })
+}
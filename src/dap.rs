@@ -0,0 +1,497 @@
+// A Debug Adapter Protocol server, for debugging programs from editors.
+//
+// https://microsoft.github.io/debug-adapter-protocol/specification
+//
+// Every message is a JSON body behind a `Content-Length: <bytes>\r\n\r\n`
+// header. Requests are read on a thread of their own and handled one at a
+// time on the calling thread. A running program takes `RUN_STEPS` steps at
+// a time, with any requests that came in handled in between, so `pause`
+// and `disconnect` get through even when it never stops.
+//
+// There is one thread, with id 1. Stack frames are the `call`s on the call
+// stack, innermost first, with frame ids counting up from the outermost.
+// Every frame shows the operand stack; the innermost one also shows the
+// locals of the newest `enter` frame.
+
+use crate::debug::{self, Debugger, Stop};
+use crate::ins::Ins;
+use crate::json::{self, Value};
+use crate::machine::Machine;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+const THREAD_ID: usize = 1;
+const STACK_REF: usize = 1;
+const LOCALS_REF: usize = 2;
+
+/// Steps a running program takes between looking for requests.
+const RUN_STEPS: usize = 10_000;
+
+/// Serves one debug session, reading requests from `input` until it ends or
+/// the client disconnects.
+///
+/// `input` is read on a thread of its own, which is left blocked on it if
+/// the client disconnects without closing it.
+pub fn serve(mut input: impl BufRead + Send + 'static, out: impl Write) -> io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || loop {
+        let msg = read_message(&mut input);
+        let last = !matches!(msg, Ok(Some(_)));
+        if tx.send(msg).is_err() || last {
+            break;
+        }
+    });
+
+    let mut session = Session {
+        out,
+        seq: 0,
+        dbg: None,
+        running: None,
+        program: String::new(),
+        stop_on_entry: false,
+        line_base: 1,
+    };
+
+    loop {
+        let msg = match session.running {
+            Some(_) => match rx.try_recv() {
+                Ok(msg) => msg,
+                Err(TryRecvError::Empty) => {
+                    session.run()?;
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(msg) => msg,
+                Err(_) => break,
+            },
+        };
+
+        let Some(msg) = msg? else {
+            break;
+        };
+        if msg.get("type").as_str() == Some("request") && !session.request(&msg)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads one message, or `None` at the end of `input`.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(v) = line.strip_prefix("Content-Length:") {
+            len = v.trim().parse::<usize>().ok();
+        }
+    }
+
+    let Some(len) = len else {
+        return Err(invalid("missing Content-Length header"));
+    };
+
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|_| invalid("message is not UTF-8"))?;
+
+    json::parse(&body).map(Some).map_err(|e| invalid(&e))
+}
+
+/// Writes `msg` with its `Content-Length` header.
+pub fn write_message(out: &mut impl Write, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    out.flush()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Error: {msg}"))
+}
+
+/// Whether a running program carries on after a step.
+type More = Box<dyn Fn(&Machine) -> bool>;
+
+struct Session<W> {
+    out: W,
+    seq: usize,
+    dbg: Option<Debugger>,
+    /// Set while the program runs.
+    running: Option<More>,
+    program: String,
+    stop_on_entry: bool,
+    /// 1 if the client counts lines from 1, as the machine does, else 0.
+    line_base: usize,
+}
+
+impl<W: Write> Session<W> {
+    /// Handles one request; returns false once the client disconnects.
+    fn request(&mut self, req: &Value) -> io::Result<bool> {
+        let args = req.get("arguments");
+
+        match req.get("command").as_str().unwrap_or_default() {
+            "initialize" => {
+                if args.get("linesStartAt1").as_bool() == Some(false) {
+                    self.line_base = 0;
+                }
+
+                let capabilities =
+                    Value::object([("supportsConfigurationDoneRequest", true.into())]);
+                self.respond(req, Ok(capabilities))?;
+            }
+            "launch" => {
+                let launched = self.launch(args);
+                let ok = launched.is_ok();
+                self.respond(req, launched)?;
+
+                // Breakpoints can only be placed once there is a program.
+                if ok {
+                    self.event("initialized", Value::Null)?;
+                }
+            }
+            "setBreakpoints" => {
+                let set = self.set_breakpoints(args);
+                self.respond(req, set)?;
+            }
+            "configurationDone" => {
+                let ready = self.debugger().map(|_| Value::Null);
+                let ok = ready.is_ok();
+                self.respond(req, ready)?;
+
+                if ok && self.stop_on_entry {
+                    self.stopped("entry", None)?;
+                } else if ok {
+                    self.running = Some(Box::new(|_| true));
+                }
+            }
+            "threads" => {
+                let thread = Value::object([("id", THREAD_ID.into()), ("name", "main".into())]);
+                let threads = Value::object([("threads", vec![thread].into())]);
+                self.respond(req, Ok(threads))?;
+            }
+            "stackTrace" => {
+                let trace = self.stack_trace();
+                self.respond(req, trace)?;
+            }
+            "scopes" => {
+                let scopes = self.scopes(args);
+                self.respond(req, scopes)?;
+            }
+            "variables" => {
+                let vars = self.variables(args);
+                self.respond(req, vars)?;
+            }
+            command @ ("continue" | "next" | "stepIn" | "stepOut") => {
+                let depth = self.debugger().map(|dbg| dbg.machine().call_stack().len());
+                let ready = depth.clone().map(|_| match command {
+                    "continue" => Value::object([("allThreadsContinued", true.into())]),
+                    _ => Value::Null,
+                });
+                self.respond(req, ready)?;
+
+                // The same conditions as `Debugger::resume`, `step_over` and
+                // `step_out`, which run to the end in one go.
+                if let Ok(depth) = depth {
+                    self.running = Some(match command {
+                        "continue" => Box::new(|_| true),
+                        "next" => Box::new(move |m| m.call_stack().len() > depth),
+                        "stepIn" => Box::new(|_| false),
+                        _ => Box::new(move |m| m.call_stack().len() >= depth),
+                    });
+                }
+            }
+            "pause" => {
+                let ready = self.debugger().map(|_| Value::Null);
+                self.respond(req, ready)?;
+
+                if self.running.take().is_some() {
+                    self.stopped("pause", None)?;
+                }
+            }
+            "disconnect" => {
+                self.respond(req, Ok(Value::Null))?;
+                return Ok(false);
+            }
+            command => {
+                self.respond(req, Err(format!("`{command}` is not supported")))?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn debugger(&self) -> Result<&Debugger, String> {
+        self.dbg
+            .as_ref()
+            .ok_or_else(|| "no program has been launched".to_string())
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let Some(program) = args.get("program").as_str() else {
+            return Err("launch needs a `program` to debug".to_string());
+        };
+
+        let (prog, info) = debug::load(program)?;
+        self.dbg = Some(Debugger::new(Machine::new(prog), info));
+        self.program = program.to_string();
+        self.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+
+        Ok(Value::Null)
+    }
+
+    /// Replaces every breakpoint, moving each one down to the first line
+    /// with an instruction on it.
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let line_base = self.line_base;
+        let Some(dbg) = self.dbg.as_mut() else {
+            return Err("no program has been launched".to_string());
+        };
+
+        // Lines of other files, such as includes, are not tracked, and
+        // must not replace the program's own breakpoints.
+        let path = args.get("source").get("path").as_str().unwrap_or_default();
+        if !same_file(path, &self.program) {
+            let placed = args
+                .get("breakpoints")
+                .as_array()
+                .iter()
+                .map(|_| {
+                    Value::object([
+                        ("verified", false.into()),
+                        (
+                            "message",
+                            "breakpoints only work in the launched program".into(),
+                        ),
+                    ])
+                })
+                .collect::<Vec<_>>();
+            return Ok(Value::object([("breakpoints", placed.into())]));
+        }
+
+        dbg.clear_breakpoints();
+
+        let mut placed = Vec::new();
+        for bp in args.get("breakpoints").as_array() {
+            let line = bp.get("line").as_usize().unwrap_or_default() + 1 - line_base;
+            let lines = &dbg.debug_info().lines;
+
+            match lines.iter().position(|l| *l >= line) {
+                Some(ip) => {
+                    let line = lines[ip] + line_base - 1;
+                    dbg.add_breakpoint(ip);
+                    placed.push(Value::object([
+                        ("verified", true.into()),
+                        ("line", line.into()),
+                    ]));
+                }
+                None => placed.push(Value::object([
+                    ("verified", false.into()),
+                    ("message", "no instruction on or after this line".into()),
+                ])),
+            }
+        }
+
+        Ok(Value::object([("breakpoints", placed.into())]))
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let dbg = self.debugger()?;
+        let m = dbg.machine();
+        let calls = m.call_stack();
+
+        let mut frames = Vec::new();
+        for depth in (0..=calls.len()).rev() {
+            // Outer frames are paused on the `call` they are waiting on.
+            let ip = match calls.get(depth) {
+                Some(ret) => ret - 1,
+                None => m.ip(),
+            };
+
+            let name = match depth.checked_sub(1).map(|d| m.program()[calls[d] - 1]) {
+                Some(Ins::Call(target)) => label_at(dbg, target),
+                _ => "main".to_string(),
+            };
+
+            let mut frame = vec![
+                ("id".to_string(), depth.into()),
+                ("name".to_string(), name.into()),
+                ("column".to_string(), 1usize.into()),
+                (
+                    "instructionPointerReference".to_string(),
+                    ip.to_string().into(),
+                ),
+            ];
+            match dbg.debug_info().lines.get(ip) {
+                Some(line) => {
+                    let source = Value::object([("path", self.program.as_str().into())]);
+                    frame.push(("line".to_string(), (line + self.line_base - 1).into()));
+                    frame.push(("source".to_string(), source));
+                }
+                None => frame.push(("line".to_string(), 0usize.into())),
+            }
+
+            frames.push(Value::Object(frame));
+        }
+
+        Ok(Value::object([
+            ("totalFrames", frames.len().into()),
+            ("stackFrames", frames.into()),
+        ]))
+    }
+
+    fn scopes(&self, args: &Value) -> Result<Value, String> {
+        let m = self.debugger()?.machine();
+        let innermost = args.get("frameId").as_usize() == Some(m.call_stack().len());
+
+        let scope = |name: &str, reference: usize| {
+            Value::object([
+                ("name", name.into()),
+                ("variablesReference", reference.into()),
+                ("expensive", false.into()),
+            ])
+        };
+
+        let mut scopes = vec![scope("Stack", STACK_REF)];
+        if innermost && !m.frames().is_empty() {
+            scopes.push(scope("Locals", LOCALS_REF));
+        }
+
+        Ok(Value::object([("scopes", scopes.into())]))
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let m = self.debugger()?.machine();
+        let stack = m.stack();
+
+        let vars: Vec<(String, usize)> = match args.get("variablesReference").as_usize() {
+            Some(STACK_REF) => (0..stack.len())
+                .map(|i| (format!("stack[{i}]"), i))
+                .collect(),
+            Some(LOCALS_REF) => match m.frames().last() {
+                Some(f) => (0..f.locals)
+                    .map(|i| (format!("local[{i}]"), f.fp + i))
+                    .collect(),
+                None => Vec::new(),
+            },
+            _ => return Err("no such variables".to_string()),
+        };
+
+        let vars = vars
+            .into_iter()
+            .filter_map(|(name, i)| stack.get(i).map(|w| (name, w)))
+            .map(|(name, w)| {
+                Value::object([
+                    ("name", name.into()),
+                    ("value", w.to_string().into()),
+                    ("type", w.kind().to_string().into()),
+                    ("variablesReference", 0usize.into()),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Value::object([("variables", vars.into())]))
+    }
+
+    /// Takes the next `RUN_STEPS` steps of a running program, telling the
+    /// client if it stopped.
+    fn run(&mut self) -> io::Result<()> {
+        let (Some(dbg), Some(more)) = (self.dbg.as_mut(), self.running.as_ref()) else {
+            return Ok(());
+        };
+        let Some(stop) = dbg.run_for(RUN_STEPS, more) else {
+            return Ok(());
+        };
+        self.running = None;
+
+        match stop {
+            Stop::Stepped | Stop::StartOfHistory => self.stopped("step", None),
+            Stop::Breakpoint(_) => self.stopped("breakpoint", None),
+            Stop::Watch(t) => self.stopped("data breakpoint", Some(t.to_string())),
+            Stop::Trapped(e) => self.stopped("exception", Some(format!("{e:?}"))),
+            Stop::Halted => {
+                self.event("terminated", Value::Null)?;
+                self.event("exited", Value::object([("exitCode", 0usize.into())]))
+            }
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        let mut body = vec![
+            ("reason".to_string(), reason.into()),
+            ("threadId".to_string(), THREAD_ID.into()),
+            ("allThreadsStopped".to_string(), true.into()),
+        ];
+        if let Some(text) = text {
+            body.push(("text".to_string(), text.into()));
+        }
+
+        self.event("stopped", Value::Object(body))
+    }
+
+    fn respond(&mut self, req: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut msg = vec![
+            ("type".to_string(), "response".into()),
+            ("request_seq".to_string(), req.get("seq").clone()),
+            ("command".to_string(), req.get("command").clone()),
+            ("success".to_string(), result.is_ok().into()),
+        ];
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => msg.push(("body".to_string(), body)),
+            Err(e) => msg.push(("message".to_string(), e.into())),
+        }
+
+        self.send(msg)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut msg = vec![
+            ("type".to_string(), "event".into()),
+            ("event".to_string(), event.into()),
+        ];
+        if body != Value::Null {
+            msg.push(("body".to_string(), body));
+        }
+
+        self.send(msg)
+    }
+
+    fn send(&mut self, mut msg: Vec<(String, Value)>) -> io::Result<()> {
+        self.seq += 1;
+        msg.insert(0, ("seq".to_string(), self.seq.into()));
+
+        write_message(&mut self.out, &Value::Object(msg))
+    }
+}
+
+/// Whether two paths name the same file, however they are spelled.
+fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// The first label, by name, on instruction `ip`, or `ip` itself.
+fn label_at(dbg: &Debugger, ip: usize) -> String {
+    dbg.debug_info()
+        .labels
+        .iter()
+        .filter(|(_, at)| **at == ip)
+        .map(|(name, _)| name.clone())
+        .min()
+        .unwrap_or_else(|| ip.to_string())
+}
@@ -0,0 +1,599 @@
+use crate::asm::{self, format_errors, DebugInfo};
+use crate::bytecode;
+use crate::ins::Ins;
+use crate::machine::{Machine, MachineErr, Undo};
+use crate::snapshot::Snapshot;
+use crate::watch::{Trigger, Watch, Watcher};
+use crate::word::Word;
+use std::collections::{BTreeSet, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+/// Instructions shown either side of `ip` by `disasm`.
+const DISASM_CONTEXT: usize = 5;
+
+/// Reads a bytecode or `.vm` file, with labels and lines for the latter.
+pub fn load(file_name: &str) -> Result<(Vec<Ins>, DebugInfo), String> {
+    if file_name.ends_with(".bin") {
+        return bytecode::read_file(file_name)
+            .map(|prog| (prog, DebugInfo::default()))
+            .map_err(|e| e.to_string());
+    }
+
+    match fs::read_to_string(file_name) {
+        Ok(src) => asm::assemble_with_debug_info(file_name, &src).map_err(|e| format_errors(&e)),
+        Err(e) => Err(format!("Error: Unable to read file {file_name:?}: {e}")),
+    }
+}
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    /// Took every step that was asked for.
+    Stepped,
+    /// About to execute the instruction at this index, which has a
+    /// breakpoint on it.
+    Breakpoint(usize),
+    Halted,
+    Trapped(MachineErr),
+    /// A watchpoint or stop condition fired.
+    Watch(Trigger),
+    /// Stepping back ran out of recorded history.
+    StartOfHistory,
+}
+
+/// Drives a `Machine` one `step` at a time, stopping at breakpoints.
+///
+/// ```
+/// use rvm::asm;
+/// use rvm::debug::{Debugger, Stop};
+/// use rvm::Machine;
+///
+/// let src = "push 1\npush 2\naddi\nhalt";
+/// let (prog, info) = asm::assemble_with_debug_info("<source>", src).unwrap();
+/// let mut dbg = Debugger::new(Machine::new(prog), info);
+///
+/// dbg.add_breakpoint(2);
+/// assert_eq!(dbg.resume(), Stop::Breakpoint(2));
+/// assert_eq!(dbg.step(10), Stop::Halted);
+/// ```
+pub struct Debugger {
+    machine: Machine,
+    start: Snapshot,
+    info: DebugInfo,
+    breakpoints: BTreeSet<usize>,
+    watcher: Watcher,
+
+    steps: u64,
+    history: VecDeque<Undo>, // Newest step last
+    history_size: usize,
+}
+
+impl Debugger {
+    pub fn new(machine: Machine, info: DebugInfo) -> Self {
+        Self {
+            start: machine.snapshot(),
+            machine,
+            info,
+            breakpoints: BTreeSet::new(),
+            watcher: Watcher::default(),
+
+            steps: 0,
+            history: VecDeque::new(),
+            history_size: 0,
+        }
+    }
+
+    /// Records the last `size` steps so they can be stepped back over.
+    pub fn with_history(mut self, size: usize) -> Self {
+        self.history_size = size;
+        self.history.truncate(size);
+        self
+    }
+
+    /// Steps taken since the start or the last `restart`.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The earliest step that can still be returned to.
+    pub fn oldest_step(&self) -> u64 {
+        self.steps - self.history.len() as u64
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.info
+    }
+
+    /// Resolves a label or an instruction index to an instruction index.
+    pub fn location(&self, s: &str) -> Result<usize, String> {
+        self.info.location(s, self.machine.program().len())
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    /// Returns false if there already was a breakpoint at `ip`.
+    pub fn add_breakpoint(&mut self, ip: usize) -> bool {
+        self.breakpoints.insert(ip)
+    }
+
+    /// Returns false if there was no breakpoint at `ip`.
+    pub fn remove_breakpoint(&mut self, ip: usize) -> bool {
+        self.breakpoints.remove(&ip)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn watcher(&self) -> &Watcher {
+        &self.watcher
+    }
+
+    /// Adds a watch, counting steps and hits from the current state.
+    pub fn add_watch(&mut self, watch: Watch) {
+        self.watcher.add(watch);
+        self.watcher.reset(&self.machine);
+    }
+
+    pub fn remove_watch(&mut self, index: usize) -> Option<Watch> {
+        self.watcher.remove(index)
+    }
+
+    /// Executes up to `n` instructions, ignoring breakpoints.
+    pub fn step(&mut self, n: usize) -> Stop {
+        for _ in 0..n {
+            if let Some(stop) = self.step_once() {
+                return stop;
+            }
+        }
+
+        Stop::Stepped
+    }
+
+    /// Executes instructions until the machine halts, traps or reaches a
+    /// breakpoint. Always takes at least one step, so resuming from a
+    /// breakpoint moves past it.
+    pub fn resume(&mut self) -> Stop {
+        self.run_while(|_| true)
+    }
+
+    /// Executes one instruction, running a `call` through to its return.
+    /// Breakpoints inside the call still stop it.
+    pub fn step_over(&mut self) -> Stop {
+        let depth = self.machine.call_stack().len();
+        self.run_while(|m| m.call_stack().len() > depth)
+    }
+
+    /// Executes instructions until the current call returns. At the top
+    /// level there is nothing to return from, so this runs like `resume`.
+    pub fn step_out(&mut self) -> Stop {
+        let depth = self.machine.call_stack().len();
+        self.run_while(|m| m.call_stack().len() >= depth)
+    }
+
+    /// Takes one step, then keeps stepping while `more` holds, stopping at
+    /// breakpoints along the way.
+    fn run_while(&mut self, more: impl Fn(&Machine) -> bool) -> Stop {
+        loop {
+            if let Some(stop) = self.run_for(usize::MAX, &more) {
+                return stop;
+            }
+        }
+    }
+
+    /// Runs like `run_while`, but gives up with `None` after `steps` steps
+    /// that did not stop, so a caller can do other work in between and
+    /// carry on with another call.
+    pub fn run_for(&mut self, steps: usize, more: impl Fn(&Machine) -> bool) -> Option<Stop> {
+        for _ in 0..steps {
+            if let Some(stop) = self.step_once() {
+                return Some(stop);
+            }
+            if !more(&self.machine) {
+                return Some(Stop::Stepped);
+            }
+
+            let ip = self.machine.ip();
+            if self.breakpoints.contains(&ip) {
+                return Some(Stop::Breakpoint(ip));
+            }
+        }
+
+        None
+    }
+
+    /// Takes back up to `n` recorded steps, ignoring breakpoints.
+    pub fn reverse_step(&mut self, n: usize) -> Stop {
+        for _ in 0..n {
+            if !self.step_back() {
+                return Stop::StartOfHistory;
+            }
+        }
+
+        Stop::Stepped
+    }
+
+    /// Steps back until reaching a breakpoint or the start of the recorded
+    /// history. Always takes at least one step back.
+    pub fn reverse_resume(&mut self) -> Stop {
+        loop {
+            if !self.step_back() {
+                return Stop::StartOfHistory;
+            }
+
+            let ip = self.machine.ip();
+            if self.breakpoints.contains(&ip) {
+                return Stop::Breakpoint(ip);
+            }
+        }
+    }
+
+    /// Moves to step number `step`, backwards through the history or
+    /// forwards by executing, ignoring breakpoints either way.
+    pub fn goto(&mut self, step: u64) -> Result<Stop, String> {
+        if step >= self.steps {
+            return Ok(self.step((step - self.steps) as usize));
+        }
+        if step < self.oldest_step() {
+            return Err(format!(
+                "step {step} is no longer recorded, history starts at step {}",
+                self.oldest_step()
+            ));
+        }
+
+        Ok(self.reverse_step((self.steps - step) as usize))
+    }
+
+    /// Puts the machine back the way it was when the debugger started,
+    /// keeping breakpoints.
+    pub fn restart(&mut self) {
+        self.machine.restore(self.start.clone());
+        self.watcher.reset(&self.machine);
+        self.steps = 0;
+        self.history.clear();
+    }
+
+    fn step_back(&mut self) -> bool {
+        let Some(undo) = self.history.pop_back() else {
+            return false;
+        };
+
+        self.machine.undo(undo);
+        self.steps -= 1;
+
+        // Counts and old values were for the timeline being undone.
+        self.watcher.reset(&self.machine);
+
+        true
+    }
+
+    fn step_once(&mut self) -> Option<Stop> {
+        if self.machine.is_halted() {
+            return Some(Stop::Halted);
+        }
+
+        // A step that traps is rolled back, leaving the machine just before
+        // the faulting instruction.
+        let (result, undo) = self.machine.step_undoable();
+        if let Err(e) = result {
+            self.machine.undo(undo);
+            return Some(Stop::Trapped(e));
+        }
+
+        self.steps += 1;
+        if self.history_size > 0 {
+            if self.history.len() == self.history_size {
+                self.history.pop_front();
+            }
+            self.history.push_back(undo);
+        }
+        if self.machine.is_halted() {
+            return Some(Stop::Halted);
+        }
+        if let Some(t) = self.watcher.check(&self.machine) {
+            return Some(Stop::Watch(t));
+        }
+
+        None
+    }
+
+    /// Reads commands from `input` until it ends or the user quits.
+    pub fn repl(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            write!(out, "(rvm) ")?;
+            out.flush()?;
+
+            let Some(line) = lines.next().transpose()? else {
+                writeln!(out)?;
+                return Ok(());
+            };
+
+            if !self.command(&line, &mut out)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs one REPL command, returning false once the user asks to quit.
+    pub fn command(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+
+        match words[..] {
+            [] => {}
+
+            ["step" | "s"] => {
+                let stop = self.step(1);
+                self.report(stop, out)?;
+            }
+            ["step" | "s", n] => match n.parse::<usize>() {
+                Ok(n) => {
+                    let stop = self.step(n);
+                    self.report(stop, out)?;
+                }
+                Err(_) => writeln!(out, "ERROR: `{n}` is not a number of steps")?,
+            },
+
+            ["continue" | "c"] => {
+                let stop = self.resume();
+                self.report(stop, out)?;
+            }
+
+            ["reverse-step" | "rs" | "reverse-continue" | "rc" | "goto", ..]
+                if self.history_size == 0 =>
+            {
+                writeln!(out, "ERROR: no history is recorded, try `rvm replay`")?
+            }
+            ["reverse-step" | "rs"] => {
+                let stop = self.reverse_step(1);
+                self.report(stop, out)?;
+            }
+            ["reverse-step" | "rs", n] => match n.parse::<usize>() {
+                Ok(n) => {
+                    let stop = self.reverse_step(n);
+                    self.report(stop, out)?;
+                }
+                Err(_) => writeln!(out, "ERROR: `{n}` is not a number of steps")?,
+            },
+            ["reverse-continue" | "rc"] => {
+                let stop = self.reverse_resume();
+                self.report(stop, out)?;
+            }
+            ["goto", n] => match n.parse::<u64>().map_err(|_| format!("`{n}` is not a step")) {
+                Ok(n) => match self.goto(n) {
+                    Ok(stop) => self.report(stop, out)?,
+                    Err(e) => writeln!(out, "ERROR: {e}")?,
+                },
+                Err(e) => writeln!(out, "ERROR: {e}")?,
+            },
+
+            ["break" | "b"] => {
+                for ip in &self.breakpoints {
+                    writeln!(out, "Breakpoint at {}", self.describe(*ip))?;
+                }
+            }
+            ["break" | "b", at] => match self.location(at) {
+                Ok(ip) => {
+                    self.add_breakpoint(ip);
+                    writeln!(out, "Breakpoint at {}", self.describe(ip))?;
+                }
+                Err(e) => writeln!(out, "ERROR: {e}")?,
+            },
+
+            ["delete" | "d"] => {
+                self.clear_breakpoints();
+                writeln!(out, "Deleted all breakpoints")?;
+            }
+            ["delete" | "d", at] => match self.location(at) {
+                Ok(ip) if self.remove_breakpoint(ip) => {
+                    writeln!(out, "Deleted breakpoint at {}", self.describe(ip))?
+                }
+                Ok(ip) => writeln!(out, "ERROR: no breakpoint at {ip}")?,
+                Err(e) => writeln!(out, "ERROR: {e}")?,
+            },
+
+            ["watch" | "w"] => {
+                for (i, w) in self.watcher.watches().enumerate() {
+                    writeln!(out, "Watch {i}: {w}")?;
+                }
+            }
+            ["watch" | "w", ..] => {
+                let spec = line.trim_start()[words[0].len()..].trim();
+                match Watch::parse(spec, |at| self.location(at)) {
+                    Ok(w) => {
+                        writeln!(out, "Watching {w}")?;
+                        self.add_watch(w);
+                    }
+                    Err(e) => writeln!(out, "ERROR: {e}")?,
+                }
+            }
+            ["unwatch", n] => match n.parse().ok().and_then(|n| self.remove_watch(n)) {
+                Some(w) => writeln!(out, "Stopped watching {w}")?,
+                None => writeln!(out, "ERROR: no watch `{n}`")?,
+            },
+
+            ["print" | "p", "stack"] => self.print_stack(out)?,
+            ["print" | "p", "ip"] => writeln!(out, "ip = {}", self.machine.ip())?,
+            ["print" | "p", "step"] => writeln!(
+                out,
+                "step = {}, history from step {}",
+                self.steps,
+                self.oldest_step()
+            )?,
+
+            ["set", ..] => match self.set(line) {
+                Ok(()) => {
+                    // Older steps did not see the new value.
+                    self.history.clear();
+                    self.print_stack(out)?
+                }
+                Err(e) => writeln!(out, "ERROR: {e}")?,
+            },
+
+            ["disasm"] | ["disasm", "around", "ip"] => {
+                self.disasm_around(self.machine.ip(), out)?
+            }
+            ["disasm", "around", at] => match self.location(at) {
+                Ok(ip) => self.disasm_around(ip, out)?,
+                Err(e) => writeln!(out, "ERROR: {e}")?,
+            },
+
+            ["restart"] => {
+                self.restart();
+                self.print_location(out)?;
+            }
+
+            ["help" | "h"] => write!(out, "{HELP}")?,
+            ["quit" | "q"] => return Ok(false),
+
+            _ => writeln!(out, "ERROR: unknown command `{line}`, try `help`")?,
+        }
+
+        Ok(true)
+    }
+
+    /// Handles `set stack[i] = <word>`.
+    fn set(&mut self, line: &str) -> Result<(), String> {
+        let usage = || "expected `set stack[i] = <word>`".to_string();
+
+        let rest = line.trim().strip_prefix("set").ok_or_else(usage)?.trim();
+        let rest = rest.strip_prefix("stack[").ok_or_else(usage)?;
+        let (index, value) = rest.split_once(']').ok_or_else(usage)?;
+        let value = value.trim().strip_prefix('=').ok_or_else(usage)?.trim();
+
+        let index = index
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("`{index}` is not a stack index"))?;
+        let word = Word::try_from(value)?;
+
+        let stack = self.machine.stack_mut();
+        let len = stack.len();
+        let slot = stack
+            .get_mut(index)
+            .ok_or_else(|| format!("stack[{index}] is not live, sp is {len}"))?;
+        *slot = word;
+
+        Ok(())
+    }
+
+    /// Describes why the debugger stopped and where.
+    pub fn report(&self, stop: Stop, out: &mut impl Write) -> io::Result<()> {
+        match stop {
+            Stop::Stepped => {}
+            Stop::Breakpoint(_) => writeln!(out, "Breakpoint reached")?,
+            Stop::Halted => return writeln!(out, "Program halted"),
+            Stop::Trapped(e) => writeln!(out, "Error: {e:?}")?,
+            Stop::Watch(t) => writeln!(out, "Watch triggered at {t}")?,
+            Stop::StartOfHistory => writeln!(out, "Reached the start of the recorded history")?,
+        }
+
+        self.print_location(out)
+    }
+
+    fn print_location(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.history_size > 0 {
+            write!(out, "[step {}] ", self.steps)?;
+        }
+
+        let ip = self.machine.ip();
+        match self.machine.program().get(ip) {
+            Some(ins) => writeln!(out, "=> {}: {ins}", self.describe(ip)),
+            None => writeln!(out, "=> {ip}: end of program"),
+        }
+    }
+
+    fn print_stack(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.machine.stack().is_empty() {
+            return writeln!(out, "stack is empty");
+        }
+
+        for (i, w) in self.machine.stack().iter().enumerate() {
+            writeln!(out, "stack[{i}] = {w}")?;
+        }
+
+        Ok(())
+    }
+
+    fn disasm_around(&self, at: usize, out: &mut impl Write) -> io::Result<()> {
+        let program = self.machine.program();
+        let start = at.saturating_sub(DISASM_CONTEXT);
+        let end = (at + DISASM_CONTEXT + 1).min(program.len());
+
+        for (i, ins) in program.iter().enumerate().take(end).skip(start) {
+            for label in self.labels_at(i) {
+                writeln!(out, "{label}:")?;
+            }
+
+            let current = if i == self.machine.ip() { "=>" } else { "  " };
+            let bp = if self.breakpoints.contains(&i) {
+                '*'
+            } else {
+                ' '
+            };
+            writeln!(out, "{current}{bp}{i:4}  {ins}")?;
+        }
+
+        Ok(())
+    }
+
+    fn labels_at(&self, ip: usize) -> Vec<&str> {
+        let mut labels = self
+            .info
+            .labels
+            .iter()
+            .filter(|(_, at)| **at == ip)
+            .map(|(l, _)| l.as_str())
+            .collect::<Vec<_>>();
+        labels.sort_unstable();
+
+        labels
+    }
+
+    /// `12`, `12 (loop)` or `12 (loop, line 5)`, whatever is known.
+    fn describe(&self, ip: usize) -> String {
+        let mut notes = self.labels_at(ip).join(", ");
+        if let Some(line) = self.info.lines.get(ip) {
+            if !notes.is_empty() {
+                notes.push_str(", ");
+            }
+            notes.push_str(&format!("line {line}"));
+        }
+
+        if notes.is_empty() {
+            ip.to_string()
+        } else {
+            format!("{ip} ({notes})")
+        }
+    }
+}
+
+const HELP: &str = "\
+step [n]                 execute n instructions, 1 by default
+continue                 run until a breakpoint, halt or error
+break [label|index]      set a breakpoint, or list them
+delete [label|index]     remove a breakpoint, or all of them
+watch [<watch>]          stop when stack[i] changes, a condition such as
+                         `top > 100` holds, or <label|index>:<n> is reached
+                         for the n-th time; list watches without one
+unwatch <n>              remove the n-th watch
+print stack|ip|step      show the stack, instruction pointer or step number
+set stack[i] = <word>    overwrite a live stack slot
+disasm [around ip|<at>]  show the instructions near ip or a location
+restart                  start the program again, keeping breakpoints
+reverse-step [n]         with history, take back n steps, 1 by default
+reverse-continue         with history, step back to the previous breakpoint
+goto <step>              with history, go back or forward to a step number
+quit                     leave the debugger
+";
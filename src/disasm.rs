@@ -0,0 +1,66 @@
+use crate::asm;
+use crate::bytecode;
+use crate::ins::Ins;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+
+/// Turns a program back into `.vm` source.
+///
+/// Every `jump`/`jumpif`/`call` target gets a synthesized `L<n>:` label, numbered
+/// in address order, so the output can be fed straight back to
+/// `asm::assemble`. Targets past the end of the program, other than the
+/// index just after the last instruction, stay numbers.
+pub fn disassemble(program: &[Ins]) -> String {
+    let labels = program
+        .iter()
+        .filter_map(|ins| match ins {
+            Ins::Jump(v) | Ins::JumpIf(v) | Ins::Call(v) => Some(*v),
+            _ => None,
+        })
+        .filter(|v| *v <= program.len())
+        .collect::<BTreeSet<usize>>()
+        .into_iter()
+        .enumerate()
+        .map(|(n, target)| (target, format!("L{n}")))
+        .collect::<BTreeMap<_, _>>();
+
+    let target = |v: &usize| labels.get(v).cloned().unwrap_or_else(|| v.to_string());
+
+    let mut out = String::new();
+
+    for (i, ins) in program.iter().enumerate() {
+        if let Some(l) = labels.get(&i) {
+            let _ = writeln!(out, "{l}:");
+        }
+
+        let _ = match ins {
+            Ins::Jump(v) => writeln!(out, "    jump {}", target(v)),
+            Ins::JumpIf(v) => writeln!(out, "    jumpif {}", target(v)),
+            Ins::Call(v) => writeln!(out, "    call {}", target(v)),
+            _ => writeln!(out, "    {ins}"),
+        };
+    }
+
+    // A target just past the last instruction still needs a place to live.
+    if let Some(l) = labels.get(&program.len()) {
+        let _ = writeln!(out, "{l}:");
+    }
+
+    out
+}
+
+/// Loads a program from either a bytecode file or `.vm` assembly text, such
+/// as a `game.bin` written before the bytecode format existed.
+pub fn read_file(path: &str) -> Result<Vec<Ins>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Error: Unable to read file {path:?}: {e}"))?;
+
+    if bytecode::is_bytecode(&bytes) {
+        return bytecode::decode(&bytes).map_err(|e| e.to_string());
+    }
+
+    match String::from_utf8(bytes) {
+        Ok(src) => asm::assemble_named(path, &src).map_err(|e| asm::format_errors(&e)),
+        Err(_) => Err(format!("Error: {path:?} is neither bytecode nor assembly")),
+    }
+}
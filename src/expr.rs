@@ -0,0 +1,188 @@
+use crate::word::Word;
+use std::fmt;
+
+/// Why a constant expression could not be evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprErr {
+    /// A name that is neither a constant, a label nor a literal.
+    Undefined(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ExprErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExprErr::Undefined(name) => write!(f, "undefined name `{name}`"),
+            ExprErr::Invalid(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+/// Evaluates a constant expression such as `LIMIT*2`, `DEPTH-1` or
+/// `(loop+1)%4`, looking names up with `lookup`.
+///
+/// `+ - * / %`, unary `-` and parentheses work on ints and floats, with
+/// the usual precedence. An int meeting a float is widened to a float; int
+/// overflow and division by zero are errors.
+///
+/// ```
+/// use rvm::expr::eval;
+/// use rvm::Word;
+///
+/// let lookup = |name: &str| (name == "LIMIT").then_some(Word::Float(100.0));
+/// assert_eq!(eval("LIMIT*2", lookup), Ok(Word::Float(200.0)));
+/// assert_eq!(eval("-(7-1)/4", lookup), Ok(Word::Int(-1)));
+/// ```
+pub fn eval(src: &str, lookup: impl Fn(&str) -> Option<Word>) -> Result<Word, ExprErr> {
+    let mut p = Parser {
+        src,
+        pos: 0,
+        lookup: &lookup,
+    };
+
+    let v = p.expr()?;
+    p.skip_ws();
+    match p.rest().chars().next() {
+        None => Ok(v),
+        Some(c) => Err(ExprErr::Invalid(format!("unexpected `{c}` in `{src}`"))),
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    lookup: &'a dyn Fn(&str) -> Option<Word>,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consumes `c` if it is the next character.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_ws();
+        if self.rest().starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expr(&mut self) -> Result<Word, ExprErr> {
+        let mut v = self.term()?;
+
+        loop {
+            if self.eat('+') {
+                v = apply('+', v, self.term()?)?;
+            } else if self.eat('-') {
+                v = apply('-', v, self.term()?)?;
+            } else {
+                return Ok(v);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Word, ExprErr> {
+        let mut v = self.unary()?;
+
+        loop {
+            let op = ['*', '/', '%'].into_iter().find(|op| self.eat(*op));
+            match op {
+                Some(op) => v = apply(op, v, self.unary()?)?,
+                None => return Ok(v),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Word, ExprErr> {
+        if !self.eat('-') {
+            return self.atom();
+        }
+
+        match self.unary()? {
+            Word::Int(v) => v
+                .checked_neg()
+                .map(Word::Int)
+                .ok_or_else(|| ExprErr::Invalid(format!("-{v} overflows"))),
+            Word::Float(v) => Ok(Word::Float(-v)),
+            w => Err(ExprErr::Invalid(format!("cannot negate {w}"))),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Word, ExprErr> {
+        if self.eat('(') {
+            let v = self.expr()?;
+            if !self.eat(')') {
+                return Err(ExprErr::Invalid(format!("missing `)` in `{}`", self.src)));
+            }
+            return Ok(v);
+        }
+
+        self.skip_ws();
+        let src = self.src;
+        let rest = &src[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(rest.len());
+        let word = &rest[..len];
+        self.pos += len;
+
+        match word.chars().next() {
+            None => match rest.chars().next() {
+                Some(c) => Err(ExprErr::Invalid(format!(
+                    "unexpected `{c}` in `{}`",
+                    self.src
+                ))),
+                None => Err(ExprErr::Invalid(format!("`{}` is incomplete", self.src))),
+            },
+            Some(c) if c.is_ascii_digit() => Word::try_from(word).map_err(ExprErr::Invalid),
+            // Constants and labels, then words such as `true` or `inf`.
+            Some(_) => (self.lookup)(word)
+                .or_else(|| Word::try_from(word).ok())
+                .ok_or_else(|| ExprErr::Undefined(word.to_string())),
+        }
+    }
+}
+
+fn apply(op: char, a: Word, b: Word) -> Result<Word, ExprErr> {
+    let float = |w: Word| match w {
+        Word::Int(v) => Some(v as f64),
+        Word::Float(v) => Some(v),
+        Word::Boolean(_) => None,
+    };
+
+    if let (Word::Int(x), Word::Int(y)) = (a, b) {
+        let v = match op {
+            '+' => x.checked_add(y),
+            '-' => x.checked_sub(y),
+            '*' => x.checked_mul(y),
+            _ if y == 0 => return Err(ExprErr::Invalid(format!("{a} {op} {b} divides by zero"))),
+            '/' => x.checked_div(y),
+            _ => x.checked_rem(y),
+        };
+        return v
+            .map(Word::Int)
+            .ok_or_else(|| ExprErr::Invalid(format!("{a} {op} {b} overflows")));
+    }
+
+    let (Some(x), Some(y)) = (float(a), float(b)) else {
+        return Err(ExprErr::Invalid(format!(
+            "cannot apply `{op}` to {a} and {b}"
+        )));
+    };
+
+    Ok(Word::Float(match op {
+        '+' => x + y,
+        '-' => x - y,
+        '*' => x * y,
+        '/' => x / y,
+        _ => x % y,
+    }))
+}
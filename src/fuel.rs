@@ -0,0 +1,53 @@
+use crate::ins::Ins;
+use std::collections::HashMap;
+use std::mem::{self, Discriminant};
+
+/// Fuel charged by `Machine::run` for each instruction.
+///
+/// Costs are per instruction kind, so `Ins::Push(Word::Int(0))` stands for
+/// every `push`:
+///
+/// ```
+/// use rvm::fuel::CostTable;
+/// use rvm::Ins;
+///
+/// let costs = CostTable::uniform(1).with(Ins::DivI, 8).with(Ins::Call(0), 4);
+///
+/// assert_eq!(costs.cost(&Ins::DivI), 8);
+/// assert_eq!(costs.cost(&Ins::Call(42)), 4);
+/// assert_eq!(costs.cost(&Ins::AddI), 1);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostTable {
+    default: u64,
+    costs: HashMap<Discriminant<Ins>, u64>,
+}
+
+impl Default for CostTable {
+    /// Every instruction costs 1, so fuel counts steps.
+    fn default() -> Self {
+        Self::uniform(1)
+    }
+}
+
+impl CostTable {
+    pub fn uniform(cost: u64) -> Self {
+        Self {
+            default: cost,
+            costs: HashMap::new(),
+        }
+    }
+
+    /// Sets the cost of every instruction of the same kind as `ins`.
+    pub fn with(mut self, ins: Ins, cost: u64) -> Self {
+        self.costs.insert(mem::discriminant(&ins), cost);
+        self
+    }
+
+    pub fn cost(&self, ins: &Ins) -> u64 {
+        self.costs
+            .get(&mem::discriminant(ins))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
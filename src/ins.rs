@@ -0,0 +1,193 @@
+use crate::word::Word;
+use std::fmt;
+use std::fmt::Display;
+
+/// How `f2i` turns a float into an int.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Rounding {
+    Trunc,
+    Round,
+    Floor,
+    Ceil,
+}
+
+impl Rounding {
+    pub fn apply(&self, x: f64) -> f64 {
+        match self {
+            Rounding::Trunc => x.trunc(),
+            Rounding::Round => x.round(),
+            Rounding::Floor => x.floor(),
+            Rounding::Ceil => x.ceil(),
+        }
+    }
+}
+
+impl Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rounding::Trunc => write!(f, "trunc"),
+            Rounding::Round => write!(f, "round"),
+            Rounding::Floor => write!(f, "floor"),
+            Rounding::Ceil => write!(f, "ceil"),
+        }
+    }
+}
+
+impl TryFrom<&str> for Rounding {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "trunc" => Ok(Rounding::Trunc),
+            "round" => Ok(Rounding::Round),
+            "floor" => Ok(Rounding::Floor),
+            "ceil" => Ok(Rounding::Ceil),
+            _ => Err(format!(
+                "`{s}` is not a rounding mode, expected trunc, round, floor or ceil"
+            )),
+        }
+    }
+}
+
+#[repr(usize)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ins {
+    NoOp,
+    Push(Word),
+    Pop,
+
+    AddI,
+    SubI,
+    MulI,
+    DivI,
+    ModI,
+    RemI,
+    NegI,
+
+    AddF,
+    SubF,
+    MulF,
+    DivF,
+    NegF,
+
+    BAnd,
+    BOr,
+    BXor,
+    BNot,
+    Shl,
+    Shr,
+    UShr,
+
+    I2F,
+    F2I(Rounding),
+    B2I,
+    I2B,
+
+    EqI,
+    NeI,
+    LtI,
+    LeI,
+    GtI,
+    GeI,
+
+    EqF,
+    NeF,
+    LtF,
+    LeF,
+    GtF,
+    GeF,
+
+    And,
+    Or,
+    Xor,
+    Not,
+
+    Jump(usize),
+    JumpIf(usize),
+    Call(usize),
+    Ret,
+
+    Enter(usize),
+    Leave,
+    LoadLocal(usize),
+    StoreLocal(usize),
+    LoadArg(usize),
+    StoreArg(usize),
+    Dup(usize),
+    Swap(usize),
+
+    Halt,
+}
+
+impl Display for Ins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ins::Push(v) => write!(f, "push {}", v),
+            Ins::Jump(v) => write!(f, "jump {}", v),
+            Ins::JumpIf(v) => write!(f, "jumpif {}", v),
+            Ins::Call(v) => write!(f, "call {}", v),
+            Ins::Ret => write!(f, "ret"),
+
+            Ins::Enter(v) => write!(f, "enter {}", v),
+            Ins::Leave => write!(f, "leave"),
+            Ins::LoadLocal(v) => write!(f, "load_local {}", v),
+            Ins::StoreLocal(v) => write!(f, "store_local {}", v),
+            Ins::LoadArg(v) => write!(f, "load_arg {}", v),
+            Ins::StoreArg(v) => write!(f, "store_arg {}", v),
+            Ins::Dup(v) => write!(f, "dup {}", v),
+            Ins::Swap(v) => write!(f, "swap {}", v),
+
+            Ins::EqI => write!(f, "eqi"),
+            Ins::NeI => write!(f, "nei"),
+            Ins::LtI => write!(f, "lti"),
+            Ins::LeI => write!(f, "lei"),
+            Ins::GtI => write!(f, "gti"),
+            Ins::GeI => write!(f, "gei"),
+
+            Ins::EqF => write!(f, "eqf"),
+            Ins::NeF => write!(f, "nef"),
+            Ins::LtF => write!(f, "ltf"),
+            Ins::LeF => write!(f, "lef"),
+            Ins::GtF => write!(f, "gtf"),
+            Ins::GeF => write!(f, "gef"),
+
+            Ins::And => write!(f, "and"),
+            Ins::Or => write!(f, "or"),
+            Ins::Xor => write!(f, "xor"),
+            Ins::Not => write!(f, "not"),
+
+            Ins::AddI => write!(f, "addi"),
+            Ins::SubI => write!(f, "subi"),
+            Ins::MulI => write!(f, "muli"),
+            Ins::DivI => write!(f, "divi"),
+            Ins::ModI => write!(f, "modi"),
+            Ins::RemI => write!(f, "remi"),
+            Ins::NegI => write!(f, "negi"),
+
+            Ins::AddF => write!(f, "addf"),
+            Ins::SubF => write!(f, "subf"),
+            Ins::MulF => write!(f, "mulf"),
+            Ins::DivF => write!(f, "divf"),
+            Ins::NegF => write!(f, "negf"),
+
+            Ins::BAnd => write!(f, "band"),
+            Ins::BOr => write!(f, "bor"),
+            Ins::BXor => write!(f, "bxor"),
+            Ins::BNot => write!(f, "bnot"),
+            Ins::Shl => write!(f, "shl"),
+            Ins::Shr => write!(f, "shr"),
+            Ins::UShr => write!(f, "ushr"),
+
+            Ins::I2F => write!(f, "i2f"),
+            Ins::F2I(Rounding::Trunc) => write!(f, "f2i"),
+            Ins::F2I(v) => write!(f, "f2i {}", v),
+            Ins::B2I => write!(f, "b2i"),
+            Ins::I2B => write!(f, "i2b"),
+
+            Ins::Pop => write!(f, "pop"),
+
+            Ins::Halt => write!(f, "halt"),
+            Ins::NoOp => write!(f, "noop"),
+        }
+    }
+}
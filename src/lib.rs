@@ -0,0 +1,34 @@
+//! A stack based virtual machine.
+//!
+//! <https://en.wikipedia.org/wiki/Stack_machine>
+//!
+//! The `rvm` binary is a thin CLI over this crate; the same pieces can be
+//! embedded directly:
+//!
+//! ```
+//! let prog = rvm::asm::assemble("push 1\npush 2\naddi\nhalt").unwrap();
+//!
+//! let mut m = rvm::Machine::builder().program(prog).build();
+//! assert_eq!(m.run(100), rvm::RunOutcome::Halted);
+//!
+//! assert_eq!(m.stack(), &[rvm::Word::Int(3)]);
+//! ```
+
+pub mod asm;
+pub mod bytecode;
+pub mod dap;
+pub mod debug;
+pub mod disasm;
+pub mod expr;
+pub mod fuel;
+pub mod ins;
+pub mod json;
+pub mod machine;
+pub mod object;
+pub mod snapshot;
+pub mod watch;
+pub mod word;
+
+pub use ins::Ins;
+pub use machine::{InterruptHandle, Machine, MachineBuilder, MachineErr, RunOutcome};
+pub use word::Word;
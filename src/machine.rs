@@ -0,0 +1,1096 @@
+use crate::bytecode::{self, BytecodeErr};
+use crate::fuel::CostTable;
+use crate::ins::Ins;
+use crate::snapshot::Snapshot;
+use crate::word::{Kind, Word};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+pub const DEFAULT_STACK_SIZE: usize = 24;
+pub const DEFAULT_CALL_STACK_SIZE: usize = 64;
+/// Most words a stack may hold, however it is built or restored.
+pub const MAX_STACK_SIZE: usize = 1 << 24;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MachineErr {
+    StackOverflow,
+    StackUnderflow,
+    CallStackOverflow,
+    CallStackUnderflow,
+    NoFrame,
+    LocalOutOfBounds(usize),
+    ArgOutOfBounds(usize),
+    TypeMismatch {
+        ins: Ins,
+        expected: Kind,
+        found: Kind,
+    },
+    IntegerOverflow {
+        ip: usize,
+    },
+    DivisionByZero {
+        ip: usize,
+    },
+    NonFiniteFloat {
+        ip: usize,
+    },
+    /// `ip` ran past the end of the program without a `halt`.
+    IpOutOfBounds(usize),
+}
+
+/// Why `Machine::run` returned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunOutcome {
+    Halted,
+    /// The next instruction costs more fuel than is left. `run` again with
+    /// more fuel to carry on from `remaining_ip`.
+    OutOfFuel {
+        remaining_ip: usize,
+    },
+    Trapped(MachineErr),
+    /// An `InterruptHandle` was triggered. The machine stopped between
+    /// instructions and `run` can pick up where it left off.
+    Interrupted,
+}
+
+/// Asks a running machine to stop, from any thread.
+///
+/// ```
+/// use rvm::{Ins, Machine, RunOutcome};
+///
+/// let mut m = Machine::new(vec![Ins::Jump(0)]);
+/// let handle = m.interrupt_handle();
+///
+/// std::thread::spawn(move || handle.interrupt());
+///
+/// assert_eq!(m.run(u64::MAX), RunOutcome::Interrupted);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Makes `Machine::run` return `RunOutcome::Interrupted` at the next
+    /// instruction boundary, or straight away if it is not running yet.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Clears a pending interrupt, returning whether there was one.
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// How float arithmetic results are rounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FloatMode {
+    /// Plain IEEE-754 results.
+    #[default]
+    Strict,
+    /// Round every result to this many decimal digits, at most
+    /// `MAX_FLOAT_DIGITS`.
+    Digits(u32),
+}
+
+/// Decimal digits `FloatMode::Digits` rounds to at most; an `f64` carries
+/// no more than 17 significant ones.
+pub const MAX_FLOAT_DIGITS: u32 = 17;
+
+impl FloatMode {
+    fn apply(&self, x: f64) -> f64 {
+        match self {
+            FloatMode::Strict => x,
+            FloatMode::Digits(d) => {
+                let factor = 10f64.powi((*d).min(MAX_FLOAT_DIGITS) as i32);
+                let scaled = (x * factor).round();
+
+                // Too large to carry that many digits, it is already exact.
+                if scaled.is_finite() {
+                    scaled / factor
+                } else {
+                    x
+                }
+            }
+        }
+    }
+}
+
+/// What integer arithmetic does when a result does not fit in an `i64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Stop with `MachineErr::IntegerOverflow`.
+    #[default]
+    Trap,
+    /// Two's complement wrap around.
+    Wrap,
+    /// Clamp to `i64::MIN`/`i64::MAX`.
+    Saturate,
+}
+
+impl TryFrom<&str> for Overflow {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "trap" => Ok(Overflow::Trap),
+            "wrap" => Ok(Overflow::Wrap),
+            "saturate" => Ok(Overflow::Saturate),
+            _ => Err(format!(
+                "`{s}` is not an overflow mode, expected trap, wrap or saturate"
+            )),
+        }
+    }
+}
+
+/// An integer operation under each `Overflow` mode.
+struct IntOp {
+    checked: fn(i64, i64) -> Option<i64>,
+    wrapping: fn(i64, i64) -> i64,
+    saturating: fn(i64, i64) -> i64,
+    divides: bool,
+}
+
+const ADD: IntOp = IntOp {
+    checked: i64::checked_add,
+    wrapping: i64::wrapping_add,
+    saturating: i64::saturating_add,
+    divides: false,
+};
+
+const SUB: IntOp = IntOp {
+    checked: i64::checked_sub,
+    wrapping: i64::wrapping_sub,
+    saturating: i64::saturating_sub,
+    divides: false,
+};
+
+const MUL: IntOp = IntOp {
+    checked: i64::checked_mul,
+    wrapping: i64::wrapping_mul,
+    saturating: i64::saturating_mul,
+    divides: false,
+};
+
+const DIV: IntOp = IntOp {
+    checked: i64::checked_div,
+    wrapping: i64::wrapping_div,
+    saturating: i64::saturating_div,
+    divides: true,
+};
+
+// `i64::MIN % -1` only overflows on the way to its result of 0, so
+// saturating is the same as wrapping.
+const MOD: IntOp = IntOp {
+    checked: i64::checked_rem_euclid,
+    wrapping: i64::wrapping_rem_euclid,
+    saturating: i64::wrapping_rem_euclid,
+    divides: true,
+};
+
+const REM: IntOp = IntOp {
+    checked: i64::checked_rem,
+    wrapping: i64::wrapping_rem,
+    saturating: i64::wrapping_rem,
+    divides: true,
+};
+
+impl IntOp {
+    fn apply(&self, mode: Overflow, ip: usize, a: i64, b: i64) -> Result<i64, MachineErr> {
+        if self.divides && b == 0 {
+            return Err(MachineErr::DivisionByZero { ip });
+        }
+
+        match ((self.checked)(a, b), mode) {
+            (Some(v), _) => Ok(v),
+            (None, Overflow::Trap) => Err(MachineErr::IntegerOverflow { ip }),
+            (None, Overflow::Wrap) => Ok((self.wrapping)(a, b)),
+            (None, Overflow::Saturate) => Ok((self.saturating)(a, b)),
+        }
+    }
+}
+
+/// An activation record created by `enter` and dropped by `leave`.
+///
+/// Locals live at `stack[fp..fp + locals]`; arguments are the words pushed
+/// just below `fp`, with argument 0 nearest to it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub fp: usize,
+    pub locals: usize,
+}
+
+/// Enough of the state before a `step` to take it back, see
+/// `Machine::step_undoable`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Undo {
+    ip: usize,
+    last_ip: Option<usize>,
+    sp: usize,
+    halt: bool,
+    capacity: usize,
+    calls: usize,
+    last_call: Option<usize>,
+    frames: usize,
+    last_frame: Option<Frame>,
+    /// Slots the step overwrote and their old words, oldest write first.
+    slots: Vec<(usize, Word)>,
+}
+
+impl Undo {
+    /// The `ip` of the instruction the step executed.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+}
+
+/// Called after every successful `step` taken by `Machine::run`.
+pub type StepHook = Box<dyn FnMut(&Machine)>;
+
+pub struct Machine {
+    stack: Vec<Word>, // Stack to hold instructions
+    sp: usize,
+    max_stack_size: usize,
+
+    program: Vec<Ins>,      //Program stack as list of instructions
+    ip: usize,              // Instruction Pointer
+    last_ip: Option<usize>, // Instruction the last step executed
+
+    calls: Vec<usize>, // Return addresses, innermost call last
+    call_stack_size: usize,
+
+    frames: Vec<Frame>, // Active frames, innermost last
+
+    halt: bool,
+
+    overflow: Overflow,
+    float_mode: FloatMode,
+    trap_non_finite: bool,
+
+    fuel: u64,
+    costs: CostTable,
+    hooks: Vec<StepHook>,
+    interrupt: InterruptHandle,
+
+    journal: Option<Vec<(usize, Word)>>, // Overwritten slots while recording a step
+}
+
+/// Configures a `Machine` before it starts executing.
+///
+/// ```
+/// use rvm::fuel::CostTable;
+/// use rvm::{Ins, Machine, Word};
+///
+/// let m = Machine::builder()
+///     .program(vec![Ins::Push(Word::Int(1)), Ins::Halt])
+///     .stack_size(8)
+///     .cost_table(CostTable::uniform(1).with(Ins::Halt, 0))
+///     .on_step(|m| m.dump())
+///     .build();
+/// ```
+pub struct MachineBuilder {
+    program: Vec<Ins>,
+    stack_size: usize,
+    max_stack_size: Option<usize>,
+    call_stack_size: usize,
+    overflow: Overflow,
+    float_mode: FloatMode,
+    trap_non_finite: bool,
+    costs: CostTable,
+    hooks: Vec<StepHook>,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        Self {
+            program: Vec::new(),
+            stack_size: DEFAULT_STACK_SIZE,
+            max_stack_size: None,
+            call_stack_size: DEFAULT_CALL_STACK_SIZE,
+            overflow: Overflow::default(),
+            float_mode: FloatMode::default(),
+            trap_non_finite: false,
+            costs: CostTable::default(),
+            hooks: Vec::new(),
+        }
+    }
+}
+
+impl MachineBuilder {
+    pub fn program(mut self, program: Vec<Ins>) -> Self {
+        self.program = program;
+        self
+    }
+
+    /// Number of words the stack starts with, and the most it can hold
+    /// unless `max_stack_size` lets it grow. Capped at `MAX_STACK_SIZE`.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = size;
+        self
+    }
+
+    /// Lets the stack grow on demand, doubling each time, up to `size` words
+    /// or `MAX_STACK_SIZE`, whichever is less.
+    pub fn max_stack_size(mut self, size: usize) -> Self {
+        self.max_stack_size = Some(size);
+        self
+    }
+
+    /// Maximum depth of nested `call`s, and separately of nested `enter`s,
+    /// before `CallStackOverflow`.
+    pub fn call_stack_size(mut self, size: usize) -> Self {
+        self.call_stack_size = size;
+        self
+    }
+
+    /// What `addi`, `subi`, `muli`, `divi`, `modi`, `remi` and `negi` do when
+    /// the result does not fit in an `i64`.
+    pub fn overflow(mut self, mode: Overflow) -> Self {
+        self.overflow = mode;
+        self
+    }
+
+    /// How `addf`, `subf`, `mulf`, `divf` and `negf` round their results.
+    pub fn float_mode(mut self, mode: FloatMode) -> Self {
+        self.float_mode = mode;
+        self
+    }
+
+    /// Stop with `MachineErr::NonFiniteFloat` when float arithmetic produces
+    /// NaN or an infinity, instead of pushing it.
+    pub fn trap_non_finite(mut self, trap: bool) -> Self {
+        self.trap_non_finite = trap;
+        self
+    }
+
+    /// Fuel `Machine::run` charges for each instruction, one per step by
+    /// default.
+    pub fn cost_table(mut self, costs: CostTable) -> Self {
+        self.costs = costs;
+        self
+    }
+
+    /// Registers a debug hook, run after every step taken by `Machine::run`.
+    pub fn on_step<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&Machine) + 'static,
+    {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    pub fn build(self) -> Machine {
+        let stack_size = self.stack_size.min(MAX_STACK_SIZE);
+
+        Machine {
+            stack: vec![Word::Int(0); stack_size],
+            sp: 0,
+            max_stack_size: self
+                .max_stack_size
+                .unwrap_or(stack_size)
+                .clamp(stack_size, MAX_STACK_SIZE),
+
+            program: self.program,
+            ip: 0,
+            last_ip: None,
+
+            calls: Vec::with_capacity(self.call_stack_size),
+            call_stack_size: self.call_stack_size,
+
+            frames: Vec::new(),
+
+            halt: false,
+
+            overflow: self.overflow,
+            float_mode: self.float_mode,
+            trap_non_finite: self.trap_non_finite,
+
+            fuel: 0,
+            costs: self.costs,
+            hooks: self.hooks,
+            interrupt: InterruptHandle::default(),
+
+            journal: None,
+        }
+    }
+}
+
+impl fmt::Debug for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Machine")
+            .field("stack", &self.stack())
+            .field("sp", &self.sp)
+            .field("capacity", &self.stack.len())
+            .field("max_stack_size", &self.max_stack_size)
+            .field("program", &self.program)
+            .field("ip", &self.ip)
+            .field("calls", &self.calls)
+            .field("frames", &self.frames)
+            .field("halt", &self.halt)
+            .field("overflow", &self.overflow)
+            .field("float_mode", &self.float_mode)
+            .field("trap_non_finite", &self.trap_non_finite)
+            .field("fuel", &self.fuel)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Machine {
+    pub fn new(program: Vec<Ins>) -> Self {
+        Self::builder().program(program).build()
+    }
+
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder::default()
+    }
+
+    /// The live part of the stack, bottom first.
+    pub fn stack(&self) -> &[Word] {
+        &self.stack[..self.sp]
+    }
+
+    /// The live words, for debuggers that patch values in place.
+    pub fn stack_mut(&mut self) -> &mut [Word] {
+        &mut self.stack[..self.sp]
+    }
+
+    /// Words the stack can hold before it has to grow.
+    pub fn stack_capacity(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// Index of the instruction the last step executed, which `ip` no
+    /// longer points past after a jump or call.
+    pub fn last_ip(&self) -> Option<usize> {
+        self.last_ip
+    }
+
+    /// Return addresses of the active calls, innermost last.
+    pub fn call_stack(&self) -> &[usize] {
+        &self.calls
+    }
+
+    /// Active frames, innermost last.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Frame pointer of the innermost frame.
+    pub fn fp(&self) -> Option<usize> {
+        self.frames.last().map(|f| f.fp)
+    }
+
+    pub fn program(&self) -> &[Ins] {
+        &self.program
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halt
+    }
+
+    /// Captures the program and everything needed to resume it, see
+    /// `snapshot::write_file` to persist it.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            program: self.program.clone(),
+            ip: self.ip,
+            halt: self.halt,
+            fuel: self.fuel,
+            stack: self.stack().to_vec(),
+            stack_capacity: self.stack.len(),
+            max_stack_size: self.max_stack_size,
+            calls: self.calls.clone(),
+            call_stack_size: self.call_stack_size,
+            frames: self.frames.clone(),
+            overflow: self.overflow,
+            float_mode: self.float_mode,
+            trap_non_finite: self.trap_non_finite,
+        }
+    }
+
+    /// Replaces the program and all execution state with a snapshot's,
+    /// keeping this machine's hooks, cost table and interrupt handles.
+    pub fn restore(&mut self, s: Snapshot) {
+        self.stack = s.stack;
+        self.sp = self.stack.len();
+        self.stack
+            .resize(s.stack_capacity.max(self.sp), Word::Int(0));
+        self.max_stack_size = s.max_stack_size.max(self.stack.len());
+
+        self.program = s.program;
+        self.ip = s.ip;
+        self.last_ip = None;
+
+        self.calls = s.calls;
+        self.call_stack_size = s.call_stack_size;
+        self.frames = s.frames;
+
+        self.halt = s.halt;
+
+        self.overflow = s.overflow;
+        self.float_mode = s.float_mode;
+        self.trap_non_finite = s.trap_non_finite;
+
+        self.fuel = s.fuel;
+    }
+
+    /// Writes the program as bytecode, returning the number of bytes written.
+    pub fn save_prog_to_file(&self, file: &str) -> Result<usize, BytecodeErr> {
+        bytecode::write_file(file, &self.program)
+    }
+
+    /// A handle that stops `run` from another thread. Every handle returned
+    /// refers to this machine.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Registers another debug hook once the machine is built, e.g. one that
+    /// holds this machine's `interrupt_handle`.
+    pub fn on_step<F>(&mut self, hook: F)
+    where
+        F: FnMut(&Machine) + 'static,
+    {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Fuel left over from earlier calls to `run`.
+    pub fn fuel(&self) -> u64 {
+        self.fuel
+    }
+
+    /// Adds `fuel` to what is left and steps until the machine halts, traps,
+    /// is interrupted or cannot pay for the next instruction, running the
+    /// debug hooks after every step.
+    ///
+    /// Unspent fuel carries over, so a machine that ran out can be topped up
+    /// and resumed by calling `run` again.
+    pub fn run(&mut self, fuel: u64) -> RunOutcome {
+        self.fuel = self.fuel.saturating_add(fuel);
+
+        while !self.halt {
+            if self.interrupt.take() {
+                return RunOutcome::Interrupted;
+            }
+
+            let Some(ins) = self.program.get(self.ip) else {
+                return RunOutcome::Trapped(MachineErr::IpOutOfBounds(self.ip));
+            };
+
+            let cost = self.costs.cost(ins);
+            if cost > self.fuel {
+                return RunOutcome::OutOfFuel {
+                    remaining_ip: self.ip,
+                };
+            }
+
+            self.fuel -= cost;
+            if let Err(e) = self.step() {
+                return RunOutcome::Trapped(e);
+            }
+
+            let mut hooks = std::mem::take(&mut self.hooks);
+            for hook in hooks.iter_mut() {
+                hook(self);
+            }
+            self.hooks = hooks;
+        }
+
+        RunOutcome::Halted
+    }
+
+    pub fn step(&mut self) -> Result<(), MachineErr> {
+        let ins = *self
+            .program
+            .get(self.ip)
+            .ok_or(MachineErr::IpOutOfBounds(self.ip))?;
+        self.last_ip = Some(self.ip);
+        match ins {
+            Ins::Push(v) => {
+                self.push(v)?;
+                self.ip += 1;
+
+                Ok(())
+            }
+
+            Ins::Pop => {
+                if self.sp == 0 {
+                    return Err(MachineErr::StackUnderflow);
+                }
+
+                let _v = self.stack[self.sp - 1];
+                self.sp -= 1;
+                self.ip += 1;
+
+                Ok(())
+            }
+
+            Ins::Swap(v) => {
+                if self.sp <= v {
+                    return Err(MachineErr::StackUnderflow);
+                }
+
+                let a = self.sp - 1;
+                let b = self.sp - 1 - v;
+
+                let (x, y) = (self.stack[a], self.stack[b]);
+                self.write(a, y);
+                self.write(b, x);
+
+                self.ip += 1;
+
+                Ok(())
+            }
+
+            Ins::EqI => self.compare(ins, Kind::Int, |a, b| a == b),
+            Ins::NeI => self.compare(ins, Kind::Int, |a, b| a != b),
+            Ins::LtI => self.compare(ins, Kind::Int, |a, b| a < b),
+            Ins::LeI => self.compare(ins, Kind::Int, |a, b| a <= b),
+            Ins::GtI => self.compare(ins, Kind::Int, |a, b| a > b),
+            Ins::GeI => self.compare(ins, Kind::Int, |a, b| a >= b),
+
+            Ins::EqF => self.compare(ins, Kind::Float, |a, b| a == b),
+            Ins::NeF => self.compare(ins, Kind::Float, |a, b| a != b),
+            Ins::LtF => self.compare(ins, Kind::Float, |a, b| a < b),
+            Ins::LeF => self.compare(ins, Kind::Float, |a, b| a <= b),
+            Ins::GtF => self.compare(ins, Kind::Float, |a, b| a > b),
+            Ins::GeF => self.compare(ins, Kind::Float, |a, b| a >= b),
+
+            Ins::And => self.compare(ins, Kind::Boolean, |a, b| a.is_true() && b.is_true()),
+            Ins::Or => self.compare(ins, Kind::Boolean, |a, b| a.is_true() || b.is_true()),
+            Ins::Xor => self.compare(ins, Kind::Boolean, |a, b| a.is_true() ^ b.is_true()),
+
+            Ins::Not => self.unary(ins, Kind::Boolean, |a| Ok(Word::Boolean(!a.is_true()))),
+
+            // Shift amounts are taken modulo 64, as in Java.
+            Ins::BAnd => self.int_op(ins, |a, b| a & b),
+            Ins::BOr => self.int_op(ins, |a, b| a | b),
+            Ins::BXor => self.int_op(ins, |a, b| a ^ b),
+            Ins::Shl => self.int_op(ins, |a, b| a.wrapping_shl(b as u32)),
+            Ins::Shr => self.int_op(ins, |a, b| a.wrapping_shr(b as u32)),
+            Ins::UShr => self.int_op(ins, |a, b| (a as u64).wrapping_shr(b as u32) as i64),
+            Ins::BNot => self.int_unary(ins, |a| !a),
+
+            // `modi` is the Euclidean modulo and never negative, `remi` takes
+            // the sign of the dividend like Rust's `%`.
+            Ins::ModI => self.int_arith(ins, MOD),
+            Ins::RemI => self.int_arith(ins, REM),
+            Ins::NegI => {
+                let (mode, ip) = (self.overflow, self.ip);
+                self.unary(ins, Kind::Int, |a| match a {
+                    Word::Int(a) => SUB.apply(mode, ip, 0, a).map(Word::Int),
+                    _ => unreachable!(),
+                })
+            }
+            Ins::NegF => {
+                let finish = self.float_finish();
+                self.unary(ins, Kind::Float, |a| match a {
+                    Word::Float(a) => finish(-a).map(Word::Float),
+                    _ => unreachable!(),
+                })
+            }
+
+            Ins::I2F => self.unary(ins, Kind::Int, |a| match a {
+                Word::Int(a) => Ok(Word::Float(a as f64)),
+                _ => unreachable!(),
+            }),
+            // Out of range floats saturate to `i64::MIN`/`i64::MAX`, NaN becomes 0.
+            Ins::F2I(mode) => self.unary(ins, Kind::Float, |a| match a {
+                Word::Float(a) => Ok(Word::Int(mode.apply(a) as i64)),
+                _ => unreachable!(),
+            }),
+            Ins::B2I => self.unary(ins, Kind::Boolean, |a| Ok(Word::Int(a.is_true() as i64))),
+            Ins::I2B => self.unary(ins, Kind::Int, |a| Ok(Word::Boolean(a != Word::Int(0)))),
+
+            Ins::AddI => self.int_arith(ins, ADD),
+            Ins::SubI => self.int_arith(ins, SUB),
+            Ins::MulI => self.int_arith(ins, MUL),
+            Ins::DivI => self.int_arith(ins, DIV),
+
+            Ins::AddF => self.float_arith(ins, |a, b| a + b),
+            Ins::SubF => self.float_arith(ins, |a, b| a - b),
+            Ins::MulF => self.float_arith(ins, |a, b| a * b),
+            Ins::DivF => self.float_arith(ins, |a, b| a / b),
+
+            Ins::Jump(v) => {
+                self.ip = v;
+
+                Ok(())
+            }
+
+            Ins::JumpIf(v) => {
+                if self.sp < 1 {
+                    return Err(MachineErr::StackUnderflow);
+                }
+
+                if self.stack[self.sp - 1].is_true() {
+                    self.ip = v;
+                } else {
+                    self.ip += 1;
+                }
+
+                self.sp -= 1;
+
+                Ok(())
+            }
+
+            Ins::Call(v) => {
+                if self.calls.len() >= self.call_stack_size {
+                    return Err(MachineErr::CallStackOverflow);
+                }
+
+                self.calls.push(self.ip + 1);
+                self.ip = v;
+
+                Ok(())
+            }
+
+            Ins::Ret => match self.calls.pop() {
+                Some(v) => {
+                    self.ip = v;
+                    Ok(())
+                }
+                None => Err(MachineErr::CallStackUnderflow),
+            },
+
+            Ins::Enter(v) => {
+                if self.frames.len() >= self.call_stack_size {
+                    return Err(MachineErr::CallStackOverflow);
+                }
+                self.reserve(v)?;
+
+                self.frames.push(Frame {
+                    fp: self.sp,
+                    locals: v,
+                });
+                for i in self.sp..self.sp + v {
+                    self.write(i, Word::Int(0));
+                }
+                self.sp += v;
+                self.ip += 1;
+
+                Ok(())
+            }
+
+            Ins::Leave => {
+                let frame = *self.frames.last().ok_or(MachineErr::NoFrame)?;
+                if self.sp < frame.fp {
+                    return Err(MachineErr::StackUnderflow);
+                }
+
+                self.frames.pop();
+                self.sp = frame.fp;
+                self.ip += 1;
+
+                Ok(())
+            }
+
+            Ins::LoadLocal(v) => {
+                let i = self.local(v)?;
+
+                self.push(self.stack[i])?;
+                self.ip += 1;
+
+                Ok(())
+            }
+
+            Ins::StoreLocal(v) => {
+                let i = self.local(v)?;
+                self.frame_has_temporary()?;
+
+                self.write(i, self.stack[self.sp - 1]);
+                self.sp -= 1;
+                self.ip += 1;
+
+                Ok(())
+            }
+
+            Ins::LoadArg(v) => {
+                let i = self.arg(v)?;
+
+                self.push(self.stack[i])?;
+                self.ip += 1;
+
+                Ok(())
+            }
+
+            Ins::StoreArg(v) => {
+                let i = self.arg(v)?;
+                self.frame_has_temporary()?;
+
+                self.write(i, self.stack[self.sp - 1]);
+                self.sp -= 1;
+                self.ip += 1;
+
+                Ok(())
+            }
+
+            Ins::Dup(v) => {
+                if self.sp <= v {
+                    return Err(MachineErr::StackUnderflow);
+                }
+
+                self.push(self.stack[self.sp - 1 - v])?;
+                self.ip += 1;
+
+                Ok(())
+            }
+
+            Ins::Halt => {
+                self.halt = true;
+                self.ip += 1;
+                Ok(())
+            }
+
+            Ins::NoOp => {
+                self.ip += 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Overwrites a stack slot, noting the old word while a step is being
+    /// recorded for `undo`.
+    fn write(&mut self, i: usize, w: Word) {
+        if let Some(journal) = &mut self.journal {
+            journal.push((i, self.stack[i]));
+        }
+        self.stack[i] = w;
+    }
+
+    /// Like `step`, also returning what it takes to undo it, whether or not
+    /// the step succeeded.
+    pub fn step_undoable(&mut self) -> (Result<(), MachineErr>, Undo) {
+        let mut undo = Undo {
+            ip: self.ip,
+            last_ip: self.last_ip,
+            sp: self.sp,
+            halt: self.halt,
+            capacity: self.stack.len(),
+            calls: self.calls.len(),
+            last_call: self.calls.last().copied(),
+            frames: self.frames.len(),
+            last_frame: self.frames.last().copied(),
+            slots: Vec::new(),
+        };
+
+        self.journal = Some(Vec::new());
+        let result = self.step();
+        undo.slots = self.journal.take().unwrap_or_default();
+
+        (result, undo)
+    }
+
+    /// Takes back a step. Steps must be undone newest first.
+    pub fn undo(&mut self, undo: Undo) {
+        for (i, w) in undo.slots.into_iter().rev() {
+            self.stack[i] = w;
+        }
+        self.stack.truncate(undo.capacity);
+
+        // A step pushes or pops at most one call and one frame.
+        self.calls.truncate(undo.calls);
+        if self.calls.len() < undo.calls {
+            self.calls.extend(undo.last_call);
+        }
+        self.frames.truncate(undo.frames);
+        if self.frames.len() < undo.frames {
+            self.frames.extend(undo.last_frame);
+        }
+
+        self.ip = undo.ip;
+        self.last_ip = undo.last_ip;
+        self.sp = undo.sp;
+        self.halt = undo.halt;
+    }
+
+    /// Makes room for `n` more words above `sp`, growing the stack if it is
+    /// allowed to.
+    fn reserve(&mut self, n: usize) -> Result<(), MachineErr> {
+        let needed = self.sp + n;
+        if needed <= self.stack.len() {
+            return Ok(());
+        }
+        if needed > self.max_stack_size {
+            return Err(MachineErr::StackOverflow);
+        }
+
+        let len = (self.stack.len() * 2).clamp(needed, self.max_stack_size);
+        self.stack.resize(len, Word::Int(0));
+
+        Ok(())
+    }
+
+    fn push(&mut self, v: Word) -> Result<(), MachineErr> {
+        self.reserve(1)?;
+
+        self.write(self.sp, v);
+        self.sp += 1;
+
+        Ok(())
+    }
+
+    fn expect(ins: Ins, kind: Kind, w: Word) -> Result<(), MachineErr> {
+        if w.kind() != kind {
+            return Err(MachineErr::TypeMismatch {
+                ins,
+                expected: kind,
+                found: w.kind(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the top of the stack, which must be of `kind`, with `f(a)`.
+    fn unary<F>(&mut self, ins: Ins, kind: Kind, f: F) -> Result<(), MachineErr>
+    where
+        F: FnOnce(Word) -> Result<Word, MachineErr>,
+    {
+        if self.sp < 1 {
+            return Err(MachineErr::StackUnderflow);
+        }
+
+        let a = self.stack[self.sp - 1];
+        Self::expect(ins, kind, a)?;
+
+        let v = f(a)?;
+        self.write(self.sp - 1, v);
+        self.ip += 1;
+
+        Ok(())
+    }
+
+    /// Replaces `a b` on top of the stack, `b` topmost, with `f(a, b)`.
+    /// Both operands must be of `kind`.
+    fn binary<F>(&mut self, ins: Ins, kind: Kind, f: F) -> Result<(), MachineErr>
+    where
+        F: FnOnce(Word, Word) -> Result<Word, MachineErr>,
+    {
+        if self.sp < 2 {
+            return Err(MachineErr::StackUnderflow);
+        }
+
+        let a = self.stack[self.sp - 2];
+        let b = self.stack[self.sp - 1];
+        Self::expect(ins, kind, a)?;
+        Self::expect(ins, kind, b)?;
+
+        let v = f(a, b)?;
+        self.write(self.sp - 2, v);
+        self.sp -= 1;
+        self.ip += 1;
+
+        Ok(())
+    }
+
+    fn compare(
+        &mut self,
+        ins: Ins,
+        kind: Kind,
+        f: fn(Word, Word) -> bool,
+    ) -> Result<(), MachineErr> {
+        self.binary(ins, kind, |a, b| Ok(Word::Boolean(f(a, b))))
+    }
+
+    fn int_unary(&mut self, ins: Ins, f: fn(i64) -> i64) -> Result<(), MachineErr> {
+        self.unary(ins, Kind::Int, |a| match a {
+            Word::Int(a) => Ok(Word::Int(f(a))),
+            _ => unreachable!(),
+        })
+    }
+
+    fn int_op(&mut self, ins: Ins, f: fn(i64, i64) -> i64) -> Result<(), MachineErr> {
+        self.binary(ins, Kind::Int, |a, b| match (a, b) {
+            (Word::Int(a), Word::Int(b)) => Ok(Word::Int(f(a, b))),
+            _ => unreachable!(),
+        })
+    }
+
+    /// Like `int_op`, but honouring the machine's `Overflow` mode.
+    fn int_arith(&mut self, ins: Ins, op: IntOp) -> Result<(), MachineErr> {
+        let (mode, ip) = (self.overflow, self.ip);
+
+        self.binary(ins, Kind::Int, |a, b| match (a, b) {
+            (Word::Int(a), Word::Int(b)) => op.apply(mode, ip, a, b).map(Word::Int),
+            _ => unreachable!(),
+        })
+    }
+
+    /// Applies the machine's `FloatMode` and non-finite trap to a result.
+    fn float_finish(&self) -> impl Fn(f64) -> Result<f64, MachineErr> {
+        let (mode, trap, ip) = (self.float_mode, self.trap_non_finite, self.ip);
+
+        move |x| {
+            if trap && !x.is_finite() {
+                return Err(MachineErr::NonFiniteFloat { ip });
+            }
+
+            Ok(mode.apply(x))
+        }
+    }
+
+    fn float_arith(&mut self, ins: Ins, f: fn(f64, f64) -> f64) -> Result<(), MachineErr> {
+        let finish = self.float_finish();
+
+        self.binary(ins, Kind::Float, |a, b| match (a, b) {
+            (Word::Float(a), Word::Float(b)) => finish(f(a, b)).map(Word::Float),
+            _ => unreachable!(),
+        })
+    }
+
+    /// Stack index of local `v` in the innermost frame.
+    fn local(&self, v: usize) -> Result<usize, MachineErr> {
+        let frame = self.frames.last().ok_or(MachineErr::NoFrame)?;
+        if v >= frame.locals || frame.fp + v >= self.sp {
+            return Err(MachineErr::LocalOutOfBounds(v));
+        }
+
+        Ok(frame.fp + v)
+    }
+
+    /// Stack index of argument `v` of the innermost frame.
+    fn arg(&self, v: usize) -> Result<usize, MachineErr> {
+        let frame = self.frames.last().ok_or(MachineErr::NoFrame)?;
+        if v >= frame.fp {
+            return Err(MachineErr::ArgOutOfBounds(v));
+        }
+
+        Ok(frame.fp - 1 - v)
+    }
+
+    /// Stores take their value from above the frame's locals, never from a
+    /// local itself.
+    fn frame_has_temporary(&self) -> Result<(), MachineErr> {
+        let frame = self.frames.last().ok_or(MachineErr::NoFrame)?;
+        if self.sp <= frame.fp + frame.locals {
+            return Err(MachineErr::StackUnderflow);
+        }
+
+        Ok(())
+    }
+
+    /// Prints the instruction the last step executed and the stack after it.
+    pub fn dump(&self) {
+        let f = match self.last_ip.and_then(|ip| self.program.get(ip)) {
+            Some(ins) => format!("{ins:?}"),
+            None => String::new(),
+        };
+        print!("{f:20}");
+        print!("STACK: [");
+        for i in 0..self.sp {
+            print!("{:?}, ", self.stack[i]);
+        }
+        println!("]");
+
+        println!();
+    }
+}
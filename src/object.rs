@@ -0,0 +1,324 @@
+// Binary encoding of a relocatable object, and the linker joining objects
+// into one program.
+//
+//     magic    b"RVMO"
+//     version  u16 LE
+//     code     u32 LE length, then the code as bytecode (see bytecode.rs)
+//     symbols  u32 LE count, then a name and a u32 LE instruction index each
+//     relocs   u32 LE count, then a u32 LE instruction index and a name each
+//     checksum u32 LE            FNV-1a over everything before it
+//
+// A name is a u32 LE byte length followed by that much UTF-8.
+//
+// Every `jump`, `jumpif` and `call` target in the code counts from the
+// object's first instruction, and the linker moves it by wherever the
+// object ends up. A relocation marks one whose target is a symbol of some
+// other object instead; its operand is 0 until the linker fills it in.
+
+use crate::bytecode::{self, BytecodeErr, Framing, Reader, Truncated};
+use crate::ins::Ins;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+
+pub const MAGIC: [u8; 4] = *b"RVMO";
+pub const VERSION: u16 = 1;
+
+/// A separately assembled file, ready to link with others.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Object {
+    pub code: Vec<Ins>,
+    /// Labels other objects may use, with the index of their instruction.
+    pub symbols: Vec<(String, usize)>,
+    /// Instructions whose target is a symbol of another object.
+    pub relocations: Vec<(usize, String)>,
+}
+
+#[derive(Debug)]
+pub enum ObjectErr {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    TrailingBytes(usize),
+    BadName {
+        offset: usize,
+    },
+    ChecksumMismatch {
+        expected: u32,
+        found: u32,
+    },
+    Code(BytecodeErr),
+    /// A symbol past the end of the code, or a relocation on a missing
+    /// instruction or one without a target.
+    BadIndex(usize),
+}
+
+impl fmt::Display for ObjectErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectErr::Io(e) => write!(f, "Error: {e}"),
+            ObjectErr::BadMagic => write!(f, "Error: Not an rvm object file"),
+            ObjectErr::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "Error: Unsupported object version {v}, expected {VERSION}"
+                )
+            }
+            ObjectErr::Truncated => write!(f, "Error: Object is truncated"),
+            ObjectErr::TrailingBytes(n) => {
+                write!(f, "Error: {n} unexpected bytes at the end of the object")
+            }
+            ObjectErr::BadName { offset } => {
+                write!(f, "Error: Invalid name at offset {offset}")
+            }
+            ObjectErr::ChecksumMismatch { expected, found } => write!(
+                f,
+                "Error: Checksum mismatch, expected {expected:#010x} found {found:#010x}"
+            ),
+            ObjectErr::Code(e) => write!(f, "{e}"),
+            ObjectErr::BadIndex(ip) => {
+                write!(f, "Error: Object refers to an invalid instruction {ip}")
+            }
+        }
+    }
+}
+
+impl From<io::Error> for ObjectErr {
+    fn from(e: io::Error) -> Self {
+        ObjectErr::Io(e)
+    }
+}
+
+impl From<Framing> for ObjectErr {
+    fn from(e: Framing) -> Self {
+        match e {
+            Framing::BadMagic => ObjectErr::BadMagic,
+            Framing::Truncated => ObjectErr::Truncated,
+            Framing::ChecksumMismatch { expected, found } => {
+                ObjectErr::ChecksumMismatch { expected, found }
+            }
+        }
+    }
+}
+
+impl From<Truncated> for ObjectErr {
+    fn from(_: Truncated) -> Self {
+        ObjectErr::Truncated
+    }
+}
+
+impl From<BytecodeErr> for ObjectErr {
+    fn from(e: BytecodeErr) -> Self {
+        ObjectErr::Code(e)
+    }
+}
+
+/// Why objects could not be linked.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkErr {
+    /// `object` uses `symbol`, but no object defines it.
+    Unresolved { symbol: String, object: String },
+    /// `symbol` is defined by both objects.
+    Duplicate {
+        symbol: String,
+        first: String,
+        second: String,
+    },
+    /// The linked program would not fit a bytecode image.
+    TooLarge(usize),
+}
+
+impl fmt::Display for LinkErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkErr::Unresolved { symbol, object } => {
+                write!(f, "Error: `{symbol}` used in {object} is not defined")
+            }
+            LinkErr::Duplicate {
+                symbol,
+                first,
+                second,
+            } => write!(
+                f,
+                "Error: `{symbol}` is defined in both {first} and {second}"
+            ),
+            LinkErr::TooLarge(n) => {
+                write!(f, "Error: Program of {n} instructions is too large to link")
+            }
+        }
+    }
+}
+
+/// Joins `(name, object)` pairs into one program, in the order given, so
+/// the first object's first instruction is where it starts. Names only
+/// label errors.
+///
+/// Every error is collected before giving up, like the assembler does.
+pub fn link(objects: &[(String, Object)]) -> Result<Vec<Ins>, Vec<LinkErr>> {
+    let mut errors = Vec::new();
+
+    let mut bases = Vec::with_capacity(objects.len());
+    let mut len = 0;
+    for (_, o) in objects {
+        bases.push(len);
+        len += o.code.len();
+    }
+    if u32::try_from(len).is_err() {
+        return Err(vec![LinkErr::TooLarge(len)]);
+    }
+
+    let mut symbols: HashMap<&str, (&str, usize)> = HashMap::new();
+    for ((name, o), base) in objects.iter().zip(&bases) {
+        for (symbol, ip) in &o.symbols {
+            match symbols.get(symbol.as_str()) {
+                Some((first, _)) => errors.push(LinkErr::Duplicate {
+                    symbol: symbol.clone(),
+                    first: first.to_string(),
+                    second: name.clone(),
+                }),
+                None => _ = symbols.insert(symbol, (name, base + ip)),
+            }
+        }
+    }
+
+    let mut prog = Vec::with_capacity(len);
+    for ((name, o), base) in objects.iter().zip(&bases) {
+        let start = prog.len();
+        prog.extend(o.code.iter().map(|ins| retarget(*ins, |ip| base + ip)));
+
+        for (ip, symbol) in &o.relocations {
+            match symbols.get(symbol.as_str()) {
+                Some((_, target)) => prog[start + ip] = retarget(o.code[*ip], |_| *target),
+                None => errors.push(LinkErr::Unresolved {
+                    symbol: symbol.clone(),
+                    object: name.clone(),
+                }),
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(prog)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Maps the target of a `jump`, `jumpif` or `call`; `None` for any other
+/// instruction.
+fn target(ins: Ins) -> Option<usize> {
+    match ins {
+        Ins::Jump(ip) | Ins::JumpIf(ip) | Ins::Call(ip) => Some(ip),
+        _ => None,
+    }
+}
+
+fn retarget(ins: Ins, f: impl FnOnce(usize) -> usize) -> Ins {
+    match ins {
+        Ins::Jump(ip) => Ins::Jump(f(ip)),
+        Ins::JumpIf(ip) => Ins::JumpIf(f(ip)),
+        Ins::Call(ip) => Ins::Call(f(ip)),
+        ins => ins,
+    }
+}
+
+pub fn encode(o: &Object) -> Result<Vec<u8>, ObjectErr> {
+    let code = bytecode::encode(&o.code)?;
+    let code_len =
+        u32::try_from(code.len()).map_err(|_| BytecodeErr::ProgramTooLarge(o.code.len()))?;
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&code_len.to_le_bytes());
+    out.extend_from_slice(&code);
+
+    // Indices fit, as the code's length does.
+    let index = |out: &mut Vec<u8>, v: usize| out.extend_from_slice(&(v as u32).to_le_bytes());
+    let name = |out: &mut Vec<u8>, s: &str| -> Result<(), ObjectErr> {
+        let len = u32::try_from(s.len()).map_err(|_| ObjectErr::BadName { offset: out.len() })?;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(s.as_bytes());
+        Ok(())
+    };
+
+    index(&mut out, o.symbols.len());
+    for (symbol, ip) in &o.symbols {
+        name(&mut out, symbol)?;
+        index(&mut out, *ip);
+    }
+
+    index(&mut out, o.relocations.len());
+    for (ip, symbol) in &o.relocations {
+        index(&mut out, *ip);
+        name(&mut out, symbol)?;
+    }
+
+    let checksum = bytecode::fnv1a(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+
+    Ok(out)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Object, ObjectErr> {
+    let mut r = bytecode::open(bytes, MAGIC)?;
+
+    let version = u16::from_le_bytes(r.take()?);
+    if version != VERSION {
+        return Err(ObjectErr::UnsupportedVersion(version));
+    }
+
+    let code_len = r.u32()?;
+    let code = bytecode::decode(r.slice(code_len)?)?;
+
+    let count = r.u32()?;
+    let mut symbols = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        let symbol = name(&mut r)?;
+        let ip = r.u32()?;
+        // A label at the end of the code is just past its last instruction.
+        if ip > code.len() {
+            return Err(ObjectErr::BadIndex(ip));
+        }
+        symbols.push((symbol, ip));
+    }
+
+    let count = r.u32()?;
+    let mut relocations = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        let ip = r.u32()?;
+        if code.get(ip).and_then(|ins| target(*ins)).is_none() {
+            return Err(ObjectErr::BadIndex(ip));
+        }
+        relocations.push((ip, name(&mut r)?));
+    }
+
+    if r.remaining() != 0 {
+        return Err(ObjectErr::TrailingBytes(r.remaining()));
+    }
+
+    Ok(Object {
+        code,
+        symbols,
+        relocations,
+    })
+}
+
+pub fn write_file(path: &str, o: &Object) -> Result<usize, ObjectErr> {
+    let bytes = encode(o)?;
+    fs::write(path, &bytes)?;
+
+    Ok(bytes.len())
+}
+
+pub fn read_file(path: &str) -> Result<Object, ObjectErr> {
+    decode(&fs::read(path)?)
+}
+
+fn name(r: &mut Reader) -> Result<String, ObjectErr> {
+    let offset = r.pos();
+    let len = r.u32()?;
+    String::from_utf8(r.slice(len)?.to_vec()).map_err(|_| ObjectErr::BadName { offset })
+}
@@ -0,0 +1,342 @@
+// Binary encoding of a paused machine.
+//
+//     magic    b"RVMS"
+//     version  u16 LE
+//     program  u32 LE length, then the program as bytecode (see bytecode.rs)
+//     ip       u64 LE
+//     halt     u8
+//     fuel     u64 LE
+//     stack    u64 LE capacity, u64 LE max size, u64 LE count, then words
+//     calls    u64 LE call stack size, u64 LE count, then u64 LE addresses
+//     frames   u64 LE count, then u64 LE fp and u64 LE locals for each
+//     overflow u8                0 trap, 1 wrap, 2 saturate
+//     floats   u8 mode, u32 LE digits, u8 trap non-finite
+//     checksum u32 LE            FNV-1a over everything before it
+//
+// A word is a tag byte, 0 int, 1 float, 2 bool, followed by 8 bytes: the
+// `i64`, the `f64` bits, or 0/1 for a boolean.
+
+use crate::bytecode::{self, BytecodeErr, Framing, Reader, Truncated};
+use crate::ins::Ins;
+use crate::machine::{FloatMode, Frame, Overflow, MAX_STACK_SIZE};
+use crate::word::Word;
+use std::fmt;
+use std::fs;
+use std::io;
+
+pub const MAGIC: [u8; 4] = *b"RVMS";
+pub const VERSION: u16 = 1;
+
+/// Everything a `Machine` needs to carry on exactly where it stopped.
+///
+/// Debug hooks, the cost table and interrupt handles belong to whoever runs
+/// the machine and are not part of a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub program: Vec<Ins>,
+    pub ip: usize,
+    pub halt: bool,
+    pub fuel: u64,
+
+    /// Live words, `stack[..sp]`.
+    pub stack: Vec<Word>,
+    pub stack_capacity: usize,
+    pub max_stack_size: usize,
+
+    pub calls: Vec<usize>,
+    pub call_stack_size: usize,
+    pub frames: Vec<Frame>,
+
+    pub overflow: Overflow,
+    pub float_mode: FloatMode,
+    pub trap_non_finite: bool,
+}
+
+#[derive(Debug)]
+pub enum SnapshotErr {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    TrailingBytes(usize),
+    BadValue {
+        offset: usize,
+    },
+    ChecksumMismatch {
+        expected: u32,
+        found: u32,
+    },
+    Program(BytecodeErr),
+    /// The fields decode but describe a machine that cannot exist.
+    Inconsistent(&'static str),
+}
+
+impl fmt::Display for SnapshotErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotErr::Io(e) => write!(f, "Error: {e}"),
+            SnapshotErr::BadMagic => write!(f, "Error: Not an rvm snapshot file"),
+            SnapshotErr::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "Error: Unsupported snapshot version {v}, expected {VERSION}"
+                )
+            }
+            SnapshotErr::Truncated => write!(f, "Error: Snapshot is truncated"),
+            SnapshotErr::TrailingBytes(n) => {
+                write!(f, "Error: {n} unexpected bytes at the end of the snapshot")
+            }
+            SnapshotErr::BadValue { offset } => {
+                write!(f, "Error: Invalid value at offset {offset}")
+            }
+            SnapshotErr::ChecksumMismatch { expected, found } => write!(
+                f,
+                "Error: Checksum mismatch, expected {expected:#010x} found {found:#010x}"
+            ),
+            SnapshotErr::Program(e) => write!(f, "{e}"),
+            SnapshotErr::Inconsistent(what) => write!(f, "Error: Snapshot is invalid: {what}"),
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotErr {
+    fn from(e: io::Error) -> Self {
+        SnapshotErr::Io(e)
+    }
+}
+
+impl From<Framing> for SnapshotErr {
+    fn from(e: Framing) -> Self {
+        match e {
+            Framing::BadMagic => SnapshotErr::BadMagic,
+            Framing::Truncated => SnapshotErr::Truncated,
+            Framing::ChecksumMismatch { expected, found } => {
+                SnapshotErr::ChecksumMismatch { expected, found }
+            }
+        }
+    }
+}
+
+impl From<Truncated> for SnapshotErr {
+    fn from(_: Truncated) -> Self {
+        SnapshotErr::Truncated
+    }
+}
+
+impl From<BytecodeErr> for SnapshotErr {
+    fn from(e: BytecodeErr) -> Self {
+        SnapshotErr::Program(e)
+    }
+}
+
+pub fn encode(s: &Snapshot) -> Result<Vec<u8>, SnapshotErr> {
+    let program = bytecode::encode(&s.program)?;
+    let program_len =
+        u32::try_from(program.len()).map_err(|_| BytecodeErr::ProgramTooLarge(s.program.len()))?;
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&program_len.to_le_bytes());
+    out.extend_from_slice(&program);
+
+    let u64 = |out: &mut Vec<u8>, v: usize| out.extend_from_slice(&(v as u64).to_le_bytes());
+
+    u64(&mut out, s.ip);
+    out.push(s.halt as u8);
+    out.extend_from_slice(&s.fuel.to_le_bytes());
+
+    u64(&mut out, s.stack_capacity);
+    u64(&mut out, s.max_stack_size);
+    u64(&mut out, s.stack.len());
+    for w in &s.stack {
+        match w {
+            Word::Int(v) => {
+                out.push(0);
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            Word::Float(v) => {
+                out.push(1);
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            Word::Boolean(v) => {
+                out.push(2);
+                out.extend_from_slice(&(*v as u64).to_le_bytes());
+            }
+        }
+    }
+
+    u64(&mut out, s.call_stack_size);
+    u64(&mut out, s.calls.len());
+    for ret in &s.calls {
+        u64(&mut out, *ret);
+    }
+
+    u64(&mut out, s.frames.len());
+    for frame in &s.frames {
+        u64(&mut out, frame.fp);
+        u64(&mut out, frame.locals);
+    }
+
+    out.push(match s.overflow {
+        Overflow::Trap => 0,
+        Overflow::Wrap => 1,
+        Overflow::Saturate => 2,
+    });
+    match s.float_mode {
+        FloatMode::Strict => {
+            out.push(0);
+            out.extend_from_slice(&0u32.to_le_bytes());
+        }
+        FloatMode::Digits(d) => {
+            out.push(1);
+            out.extend_from_slice(&d.to_le_bytes());
+        }
+    }
+    out.push(s.trap_non_finite as u8);
+
+    let checksum = bytecode::fnv1a(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+
+    Ok(out)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Snapshot, SnapshotErr> {
+    let mut r = bytecode::open(bytes, MAGIC)?;
+
+    let version = u16::from_le_bytes(r.take()?);
+    if version != VERSION {
+        return Err(SnapshotErr::UnsupportedVersion(version));
+    }
+
+    let program_len = r.u32()?;
+    let program = bytecode::decode(r.slice(program_len)?)?;
+
+    let ip = usize(&mut r)?;
+    let halt = bool(&mut r)?;
+    let fuel = u64::from_le_bytes(r.take()?);
+
+    let stack_capacity = usize(&mut r)?;
+    let max_stack_size = usize(&mut r)?;
+    let count = usize(&mut r)?;
+    let mut stack = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        let offset = r.pos();
+        let [tag] = r.take()?;
+        let bits: [u8; 8] = r.take()?;
+
+        stack.push(match (tag, u64::from_le_bytes(bits)) {
+            (0, _) => Word::Int(i64::from_le_bytes(bits)),
+            (1, _) => Word::Float(f64::from_le_bytes(bits)),
+            (2, 0) => Word::Boolean(false),
+            (2, 1) => Word::Boolean(true),
+            _ => return Err(SnapshotErr::BadValue { offset }),
+        });
+    }
+
+    let call_stack_size = usize(&mut r)?;
+    let count = usize(&mut r)?;
+    let mut calls = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        calls.push(usize(&mut r)?);
+    }
+
+    let count = usize(&mut r)?;
+    let mut frames = Vec::with_capacity(count.min(bytes.len()));
+    for _ in 0..count {
+        frames.push(Frame {
+            fp: usize(&mut r)?,
+            locals: usize(&mut r)?,
+        });
+    }
+
+    let offset = r.pos();
+    let overflow = match r.take()? {
+        [0] => Overflow::Trap,
+        [1] => Overflow::Wrap,
+        [2] => Overflow::Saturate,
+        _ => return Err(SnapshotErr::BadValue { offset }),
+    };
+
+    let offset = r.pos();
+    let [mode] = r.take()?;
+    let digits = u32::from_le_bytes(r.take()?);
+    let float_mode = match mode {
+        0 => FloatMode::Strict,
+        1 => FloatMode::Digits(digits),
+        _ => return Err(SnapshotErr::BadValue { offset }),
+    };
+    let trap_non_finite = bool(&mut r)?;
+
+    if r.remaining() != 0 {
+        return Err(SnapshotErr::TrailingBytes(r.remaining()));
+    }
+
+    let s = Snapshot {
+        program,
+        ip,
+        halt,
+        fuel,
+        stack,
+        stack_capacity,
+        max_stack_size,
+        calls,
+        call_stack_size,
+        frames,
+        overflow,
+        float_mode,
+        trap_non_finite,
+    };
+    check(&s)?;
+
+    Ok(s)
+}
+
+/// Rejects snapshots whose sizes and pointers do not agree with each other.
+fn check(s: &Snapshot) -> Result<(), SnapshotErr> {
+    if s.stack.len() > s.stack_capacity || s.stack_capacity > s.max_stack_size {
+        return Err(SnapshotErr::Inconsistent("stack does not fit its capacity"));
+    }
+    if s.max_stack_size > MAX_STACK_SIZE {
+        return Err(SnapshotErr::Inconsistent("stack is larger than allowed"));
+    }
+    if s.calls.len() > s.call_stack_size {
+        return Err(SnapshotErr::Inconsistent(
+            "call stack does not fit its capacity",
+        ));
+    }
+    let outside = |f: &Frame| {
+        f.fp.checked_add(f.locals)
+            .is_none_or(|end| end > s.stack_capacity)
+    };
+    if s.frames.iter().any(outside) {
+        return Err(SnapshotErr::Inconsistent("frame lies outside the stack"));
+    }
+
+    Ok(())
+}
+
+pub fn write_file(path: &str, s: &Snapshot) -> Result<usize, SnapshotErr> {
+    let bytes = encode(s)?;
+    fs::write(path, &bytes)?;
+
+    Ok(bytes.len())
+}
+
+pub fn read_file(path: &str) -> Result<Snapshot, SnapshotErr> {
+    decode(&fs::read(path)?)
+}
+
+fn usize(r: &mut Reader) -> Result<usize, SnapshotErr> {
+    let offset = r.pos();
+    usize::try_from(u64::from_le_bytes(r.take()?)).map_err(|_| SnapshotErr::BadValue { offset })
+}
+
+fn bool(r: &mut Reader) -> Result<bool, SnapshotErr> {
+    let offset = r.pos();
+    match r.take()? {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        _ => Err(SnapshotErr::BadValue { offset }),
+    }
+}
@@ -0,0 +1,518 @@
+//  https://en.wikipedia.org/wiki/Stack_machine
+use rvm::asm::{self, format_errors, DebugInfo};
+use rvm::bytecode;
+use rvm::dap;
+use rvm::debug::{load, Debugger, Stop};
+use rvm::disasm;
+use rvm::machine::{FloatMode, Machine, Overflow, RunOutcome, DEFAULT_STACK_SIZE};
+use rvm::object::{self, Object};
+use rvm::snapshot;
+use rvm::watch::{Condition, Watch, Watcher};
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+
+    match args.first().map(String::as_str) {
+        Some("disasm") => disasm(&args[1..]),
+        Some("debug") => debug(&args[1..], false),
+        Some("replay") => debug(&args[1..], true),
+        Some("dap") => dap(),
+        Some("asm") => asm(&args[1..]),
+        Some("link") => link(&args[1..]),
+        _ => run(&args),
+    }
+}
+
+fn disasm(args: &[String]) {
+    let mut file_name = String::new();
+    let mut out = None;
+
+    for arg in args {
+        if let Some(o) = arg.strip_prefix("-o=") {
+            out = Some(o.to_string());
+        } else {
+            file_name = arg.clone();
+        }
+    }
+
+    if file_name.is_empty() {
+        eprintln!("USAGE: ./stack_machine disasm game.bin");
+        eprintln!("USAGE: -o=out.vm");
+        eprintln!("ERROR: Expect a input");
+
+        return;
+    }
+
+    let src = match disasm::read_file(&file_name) {
+        Ok(prog) => disasm::disassemble(&prog),
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+
+    match out {
+        Some(o) => {
+            if let Err(e) = fs::write(&o, src) {
+                eprintln!("Error: Unable to write {o:?}: {e}");
+            }
+        }
+        None => print!("{src}"),
+    }
+}
+
+/// Splits `args` into the output file, given as `-o=file` or `-o file`,
+/// and the rest.
+fn output(args: &[String]) -> (Option<String>, Vec<String>) {
+    let mut out = None;
+    let mut rest = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(o) = arg.strip_prefix("-o=") {
+            out = Some(o.to_string());
+        } else if arg == "-o" {
+            out = args.next().cloned();
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+
+    (out, rest)
+}
+
+/// Assembles a program into a bytecode image or, with `-c`, an object for
+/// `rvm link`. The output goes next to the source unless `-o` says.
+fn asm(args: &[String]) {
+    let (out, rest) = output(args);
+    let object = rest.iter().any(|a| a == "-c");
+    let Some(file_name) = rest.iter().find(|a| *a != "-c") else {
+        eprintln!("USAGE: ./stack_machine asm e.vm");
+        eprintln!("USAGE: -c, assemble an object for `link`");
+        eprintln!("USAGE: -o=out.bin | -o=out.o");
+        eprintln!("ERROR: Expect a input");
+
+        return;
+    };
+
+    let src = match fs::read_to_string(file_name) {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("Error: Unable to read {file_name:?}: {e}");
+            return;
+        }
+    };
+    let extension = if object { "o" } else { "bin" };
+    let out = out.unwrap_or_else(|| {
+        let path = std::path::Path::new(file_name).with_extension(extension);
+        path.display().to_string()
+    });
+
+    let written = if object {
+        asm::assemble_object(file_name, &src)
+            .map_err(|e| format_errors(&e))
+            .and_then(|o| object::write_file(&out, &o).map_err(|e| e.to_string()))
+    } else {
+        asm::assemble_named(file_name, &src)
+            .map_err(|e| format_errors(&e))
+            .and_then(|prog| bytecode::write_file(&out, &prog).map_err(|e| e.to_string()))
+    };
+
+    if let Err(e) = written {
+        eprintln!("{e}");
+    }
+}
+
+/// Links objects from `rvm asm -c` into one bytecode image, starting at
+/// the first object's code.
+fn link(args: &[String]) {
+    let (out, files) = output(args);
+    let (Some(out), false) = (out, files.is_empty()) else {
+        eprintln!("USAGE: ./stack_machine link a.o b.o -o prog.bin");
+        eprintln!("ERROR: Expect objects and an output");
+
+        return;
+    };
+
+    let mut objects: Vec<(String, Object)> = Vec::new();
+    for file in files {
+        match object::read_file(&file) {
+            Ok(o) => objects.push((file, o)),
+            Err(e) => {
+                eprintln!("{e}");
+                return;
+            }
+        }
+    }
+
+    let prog = match object::link(&objects) {
+        Ok(prog) => prog,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{e}");
+            }
+            return;
+        }
+    };
+
+    if let Err(e) = bytecode::write_file(&out, &prog) {
+        eprintln!("{e}");
+    }
+}
+
+/// Serves the Debug Adapter Protocol on stdin/stdout; the program to debug
+/// comes with the client's `launch` request.
+fn dap() {
+    if let Err(e) = dap::serve(io::BufReader::new(io::stdin()), io::stdout().lock()) {
+        eprintln!("{e}");
+    }
+}
+
+/// Steps `rvm replay` records before handing over, unless `-l=` says.
+const REPLAY_STEPS: usize = 1_000_000;
+/// Steps `rvm replay` can go back over, unless `--history=` says.
+const REPLAY_HISTORY: usize = 100_000;
+
+/// `rvm debug` starts at the first instruction; `rvm replay` first runs the
+/// program, recording history, and starts wherever it stopped.
+fn debug(args: &[String], replay: bool) {
+    let mut file_name = String::new();
+    let mut limit = REPLAY_STEPS;
+    let mut history = if replay { REPLAY_HISTORY } else { 0 };
+
+    for arg in args {
+        if let Some(v) = arg.strip_prefix("-l=") {
+            match v.parse::<usize>() {
+                Ok(v) => limit = v,
+                Err(_) => {
+                    eprintln!("ERROR: `{v}` is not a number of steps");
+                    return;
+                }
+            }
+        } else if let Some(v) = arg.strip_prefix("--history=") {
+            match v.parse::<usize>() {
+                Ok(v) => history = v,
+                Err(_) => {
+                    eprintln!("ERROR: `{v}` is not a number of steps");
+                    return;
+                }
+            }
+        } else {
+            file_name = arg.clone();
+        }
+    }
+
+    if file_name.is_empty() {
+        eprintln!("USAGE: ./stack_machine debug *.vm | *.bin");
+        eprintln!("USAGE: ./stack_machine replay *.vm | *.bin");
+        eprintln!("USAGE: -l=steps, how far replay runs before stopping");
+        eprintln!("USAGE: --history=steps, how many steps can be taken back");
+        eprintln!("ERROR: Expect a input");
+
+        return;
+    }
+    let file_name = &file_name;
+
+    let (prog, info) = match load(file_name) {
+        Ok(prog) => prog,
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+
+    let mut dbg = Debugger::new(Machine::new(prog), info).with_history(history);
+
+    let stop = if replay {
+        dbg.step(limit)
+    } else {
+        Stop::Stepped
+    };
+    if let Err(e) = dbg.report(stop, &mut io::stdout()) {
+        eprintln!("Error: {e}");
+        return;
+    }
+
+    if let Err(e) = dbg.repl(io::stdin().lock(), io::stdout()) {
+        eprintln!("Error: {e}");
+    }
+}
+
+fn run(args: &[String]) {
+    let mut file_name = String::new();
+    let mut limit = None;
+    let mut debug = false;
+    let mut overflow = Overflow::default();
+    let mut float_mode = FloatMode::default();
+    let mut trap_nan = false;
+    let mut stack_size = DEFAULT_STACK_SIZE;
+    let mut max_stack_size = None;
+    let mut timeout = None;
+    let mut save_snapshot = None;
+    let mut resume = None;
+    let mut watch_flags = Vec::new();
+
+    for arg in args {
+        if arg.ends_with(".vm") || arg.ends_with(".bin") {
+            file_name = arg.clone();
+        }
+
+        if arg.starts_with("-l=") {
+            eprintln!("USAGE: -l=fuel");
+            match arg.replace("-l=", "").parse::<u64>() {
+                Ok(v) => limit = Some(v),
+                Err(_) => {
+                    eprintln!("ERROR: `{arg}` is not an amount of fuel");
+                    return;
+                }
+            }
+        }
+
+        if arg == "-d" {
+            eprintln!("USAGE: debug, -d");
+            debug = true;
+        }
+
+        if let Some(v) = arg.strip_prefix("--overflow=") {
+            match Overflow::try_from(v) {
+                Ok(v) => overflow = v,
+                Err(e) => {
+                    eprintln!("ERROR: {e}");
+                    return;
+                }
+            }
+        }
+
+        if let Some(v) = arg.strip_prefix("--float-digits=") {
+            match v.parse::<u32>() {
+                Ok(v) => float_mode = FloatMode::Digits(v),
+                Err(_) => {
+                    eprintln!("ERROR: `{v}` is not a number of digits");
+                    return;
+                }
+            }
+        }
+
+        if let Some(v) = arg.strip_prefix("--stack-size=") {
+            match v.parse::<usize>() {
+                Ok(v) => stack_size = v,
+                Err(_) => {
+                    eprintln!("ERROR: `{v}` is not a stack size");
+                    return;
+                }
+            }
+        }
+
+        if let Some(v) = arg.strip_prefix("--max-stack-size=") {
+            match v.parse::<usize>() {
+                Ok(v) => max_stack_size = Some(v),
+                Err(_) => {
+                    eprintln!("ERROR: `{v}` is not a stack size");
+                    return;
+                }
+            }
+        }
+
+        if let Some(v) = arg.strip_prefix("--timeout=") {
+            match parse_duration(v) {
+                Some(v) => timeout = Some(v),
+                None => {
+                    eprintln!("ERROR: `{v}` is not a duration, try `500ms` or `2s`");
+                    return;
+                }
+            }
+        }
+
+        if let Some(v) = arg.strip_prefix("--save-snapshot=") {
+            save_snapshot = Some(v.to_string());
+        }
+
+        if let Some(v) = arg.strip_prefix("--resume=") {
+            resume = Some(v.to_string());
+        }
+
+        if arg.starts_with("--watch=")
+            || arg.starts_with("--stop-if=")
+            || arg.starts_with("--stop-at=")
+        {
+            watch_flags.push(arg.clone());
+        }
+
+        if arg == "--trap-nan" {
+            trap_nan = true;
+        }
+    }
+
+    // A timeout alone is enough of a bound to run without a fuel limit.
+    let limit = limit.or(timeout.map(|_| u64::MAX));
+
+    let Some(limit) = limit.filter(|_| file_name.len() >= 3 || resume.is_some()) else {
+        eprintln!("USAGE: ./stack_machine *.vm | *.bin");
+        eprintln!("USAGE: -l=fuel");
+        eprintln!("USAGE: debug,  -d");
+        eprintln!("USAGE: --overflow=trap|wrap|saturate");
+        eprintln!("USAGE: --float-digits=digits");
+        eprintln!("USAGE: --trap-nan");
+        eprintln!("USAGE: --stack-size=words");
+        eprintln!("USAGE: --max-stack-size=words, grow the stack up to this size");
+        eprintln!("USAGE: --timeout=500ms|2s, stop after this much wall-clock time");
+        eprintln!("USAGE: --save-snapshot=file, save the machine when the run stops");
+        eprintln!("USAGE: --resume=file, continue from a saved snapshot instead of a program");
+        eprintln!("USAGE: --watch=stack[i], stop when the slot changes");
+        eprintln!("USAGE: --stop-if='top > 100', stop when sp, ip, top or stack[i] compares true");
+        eprintln!("USAGE: --stop-at=label|index[:n], stop the n-th time it is reached");
+        eprintln!("USAGE: ./stack_machine disasm game.bin");
+        eprintln!("USAGE: ./stack_machine debug e.vm");
+        eprintln!("USAGE: ./stack_machine replay e.vm");
+        eprintln!("USAGE: ./stack_machine dap, for editors speaking the Debug Adapter Protocol");
+        eprintln!("USAGE: ./stack_machine asm -c a.vm, assemble an object to link");
+        eprintln!("USAGE: ./stack_machine link a.o b.o -o prog.bin");
+        eprintln!("ERROR: Expect a input");
+
+        return;
+    };
+
+    let resumed = match resume.as_deref().map(snapshot::read_file) {
+        Some(Ok(s)) => Some(s),
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            return;
+        }
+        None => None,
+    };
+
+    let prog = if resumed.is_some() {
+        Ok((Vec::new(), DebugInfo::default()))
+    } else {
+        load(&file_name)
+    };
+
+    let (prog, info) = match prog {
+        Ok(prog) => prog,
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+
+    let mut builder = Machine::builder()
+        .program(prog)
+        .stack_size(stack_size)
+        .overflow(overflow)
+        .float_mode(float_mode)
+        .trap_non_finite(trap_nan);
+
+    if let Some(v) = max_stack_size {
+        builder = builder.max_stack_size(v);
+    }
+
+    if debug {
+        builder = builder.on_step(|m| m.dump());
+    }
+
+    let mut m = builder.build();
+
+    // The snapshot brings its own program, stack and modes.
+    if let Some(s) = resumed {
+        m.restore(s);
+    }
+
+    let mut watcher = Watcher::default();
+    for flag in &watch_flags {
+        let len = m.program().len();
+        let watch = if let Some(v) = flag.strip_prefix("--watch=") {
+            Watch::slot(v)
+        } else if let Some(v) = flag.strip_prefix("--stop-if=") {
+            Condition::try_from(v).map(Watch::When)
+        } else {
+            Watch::hits(&flag["--stop-at=".len()..], |at| info.location(at, len))
+        };
+
+        match watch {
+            Ok(w) => watcher.add(w),
+            Err(e) => {
+                eprintln!("ERROR: {e}");
+                return;
+            }
+        }
+    }
+
+    // Watches run as a hook and stop the run through the interrupt handle.
+    let fired = Rc::new(RefCell::new(None));
+    if !watcher.is_empty() {
+        watcher.reset(&m);
+
+        let handle = m.interrupt_handle();
+        let fired = fired.clone();
+        m.on_step(move |m| {
+            if let Some(t) = watcher.check(m) {
+                *fired.borrow_mut() = Some(t);
+                handle.interrupt();
+            }
+        });
+    }
+
+    if let Some(timeout) = timeout {
+        let handle = m.interrupt_handle();
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            handle.interrupt();
+        });
+    }
+
+    let outcome = m.run(limit);
+
+    let fired = fired.take();
+    if let Some(t) = &fired {
+        println!("Stopped at {t}");
+        dump_state(&m);
+    }
+
+    match outcome {
+        RunOutcome::Halted => {}
+        RunOutcome::OutOfFuel { remaining_ip } => {
+            eprintln!("Out of fuel at instruction {remaining_ip}");
+        }
+        RunOutcome::Trapped(e) => eprintln!("Error: {:?}", e),
+        // A watch that fired was reported above.
+        RunOutcome::Interrupted if fired.is_some() => {}
+        RunOutcome::Interrupted if timeout.is_some() => {
+            eprintln!("Timed out at instruction {}", m.ip())
+        }
+        RunOutcome::Interrupted => {}
+    }
+
+    if let Some(path) = save_snapshot {
+        if let Err(e) = snapshot::write_file(&path, &m.snapshot()) {
+            eprintln!("{e}");
+        }
+    }
+
+    if let Err(e) = m.save_prog_to_file("game.bin") {
+        eprintln!("{e}");
+    }
+}
+
+fn dump_state(m: &Machine) {
+    let stack = m.stack().iter().map(|w| w.to_string()).collect::<Vec<_>>();
+
+    println!("ip: {}  sp: {}  fp: {:?}", m.ip(), m.sp(), m.fp());
+    println!("calls: {:?}", m.call_stack());
+    println!("stack: [{}]", stack.join(", "));
+}
+
+/// Parses `500ms`, `2s` or a bare number of seconds.
+fn parse_duration(s: &str) -> Option<Duration> {
+    if let Some(ms) = s.strip_suffix("ms") {
+        return ms.parse().ok().map(Duration::from_millis);
+    }
+
+    let secs = s.strip_suffix('s').unwrap_or(s).parse::<f64>().ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+}
@@ -0,0 +1,393 @@
+use crate::machine::Machine;
+use crate::word::Word;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A value read from the machine by a `Condition`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Sp,
+    Ip,
+    /// The top of the stack; conditions on it are false while it is empty.
+    Top,
+    /// A stack slot; conditions on it are false while it is not live.
+    Slot(usize),
+}
+
+impl Operand {
+    fn read(&self, m: &Machine) -> Option<Word> {
+        match self {
+            Operand::Sp => Some(Word::Int(m.sp() as i64)),
+            Operand::Ip => Some(Word::Int(m.ip() as i64)),
+            Operand::Top => m.stack().last().copied(),
+            Operand::Slot(i) => m.stack().get(*i).copied(),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Sp => write!(f, "sp"),
+            Operand::Ip => write!(f, "ip"),
+            Operand::Top => write!(f, "top"),
+            Operand::Slot(i) => write!(f, "stack[{i}]"),
+        }
+    }
+}
+
+impl TryFrom<&str> for Operand {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "sp" => Ok(Operand::Sp),
+            "ip" => Ok(Operand::Ip),
+            "top" => Ok(Operand::Top),
+            _ => slot(s)
+                .map(Operand::Slot)
+                .ok_or_else(|| format!("`{s}` is not sp, ip, top or stack[i]")),
+        }
+    }
+}
+
+/// Parses `stack[i]`.
+fn slot(s: &str) -> Option<usize> {
+    s.strip_prefix("stack[")?
+        .strip_suffix(']')?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// `<operand> <op> <word>`, e.g. `top > 100.0` or `sp >= 10`.
+///
+/// Ints and floats compare by value with each other, booleans only with
+/// booleans.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub lhs: Operand,
+    pub op: Cmp,
+    pub rhs: Word,
+}
+
+/// How a `Condition` compares its operand with its word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    fn holds(&self, ord: Ordering) -> bool {
+        match self {
+            Cmp::Eq => ord == Ordering::Equal,
+            Cmp::Ne => ord != Ordering::Equal,
+            Cmp::Lt => ord == Ordering::Less,
+            Cmp::Le => ord != Ordering::Greater,
+            Cmp::Gt => ord == Ordering::Greater,
+            Cmp::Ge => ord != Ordering::Less,
+        }
+    }
+}
+
+impl fmt::Display for Cmp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = OPS.iter().find(|(_, cmp)| cmp == self).unwrap().0;
+        write!(f, "{op}")
+    }
+}
+
+const OPS: [(&str, Cmp); 6] = [
+    ("==", Cmp::Eq),
+    ("!=", Cmp::Ne),
+    ("<=", Cmp::Le),
+    (">=", Cmp::Ge),
+    ("<", Cmp::Lt),
+    (">", Cmp::Gt),
+];
+
+impl Condition {
+    pub fn holds(&self, m: &Machine) -> bool {
+        let Some(lhs) = self.lhs.read(m) else {
+            return false;
+        };
+        let Some(ord) = compare(lhs, self.rhs) else {
+            return false;
+        };
+
+        self.op.holds(ord)
+    }
+}
+
+fn compare(a: Word, b: Word) -> Option<Ordering> {
+    match (a, b) {
+        (Word::Int(a), Word::Int(b)) => Some(a.cmp(&b)),
+        (Word::Int(a), Word::Float(b)) => (a as f64).partial_cmp(&b),
+        (Word::Float(a), Word::Int(b)) => a.partial_cmp(&(b as f64)),
+        (Word::Float(a), Word::Float(b)) => a.partial_cmp(&b),
+        (Word::Boolean(a), Word::Boolean(b)) => Some(a.cmp(&b)),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.lhs, self.op, self.rhs)
+    }
+}
+
+impl TryFrom<&str> for Condition {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let Some((at, (text, op))) = OPS
+            .iter()
+            .filter_map(|op| s.find(op.0).map(|at| (at, *op)))
+            .min_by_key(|(at, (text, _))| (*at, usize::MAX - text.len()))
+        else {
+            return Err(format!(
+                "`{s}` is not a condition, expected e.g. `top > 100` or `sp >= 10`"
+            ));
+        };
+
+        Ok(Condition {
+            lhs: Operand::try_from(s[..at].trim())?,
+            op,
+            rhs: Word::try_from(s[at + text.len()..].trim())?,
+        })
+    }
+}
+
+/// Something that stops a run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Watch {
+    /// `stack[i]` changed, including becoming live or being popped.
+    Slot(usize),
+    /// The condition became true.
+    When(Condition),
+    /// A step arrived at instruction `ip` for the `count`th time.
+    Hits { ip: usize, count: usize },
+}
+
+impl Watch {
+    /// Parses `stack[i]`, a `Condition`, or `<label|index>:<count>` for a hit
+    /// count, resolving labels with `resolve`.
+    pub fn parse(s: &str, resolve: impl Fn(&str) -> Result<usize, String>) -> Result<Self, String> {
+        let s = s.trim();
+
+        if let Some(i) = slot(s) {
+            return Ok(Watch::Slot(i));
+        }
+        if OPS.iter().any(|(text, _)| s.contains(text)) {
+            return Condition::try_from(s).map(Watch::When);
+        }
+
+        Watch::hits(s, resolve)
+    }
+
+    /// Parses `stack[i]`.
+    pub fn slot(s: &str) -> Result<Self, String> {
+        slot(s.trim())
+            .map(Watch::Slot)
+            .ok_or_else(|| format!("`{s}` is not a stack slot, expected stack[i]"))
+    }
+
+    /// Parses `<label|index>:<count>`, or just `<label|index>` for the
+    /// first hit.
+    pub fn hits(s: &str, resolve: impl Fn(&str) -> Result<usize, String>) -> Result<Self, String> {
+        let (at, count) = match s.rsplit_once(':') {
+            Some((at, count)) => {
+                let count = count
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|c| *c > 0)
+                    .ok_or_else(|| format!("`{count}` is not a hit count"))?;
+                (at, count)
+            }
+            None => (s, 1),
+        };
+
+        Ok(Watch::Hits {
+            ip: resolve(at)?,
+            count,
+        })
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watch::Slot(i) => write!(f, "stack[{i}]"),
+            Watch::When(c) => write!(f, "{c}"),
+            Watch::Hits { ip, count } => write!(f, "{ip}:{count}"),
+        }
+    }
+}
+
+/// Why a `Watcher` fired.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trigger {
+    /// Steps taken when it fired, counting from when the watcher was reset.
+    pub step: u64,
+    pub ip: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {}, ip {}: {}", self.step, self.ip, self.reason)
+    }
+}
+
+struct Armed {
+    watch: Watch,
+    /// Last value seen of a watched slot.
+    seen: Option<Word>,
+    /// Whether a condition held at the last step.
+    held: bool,
+    hits: usize,
+}
+
+/// Checks a set of `Watch`es after every step.
+///
+/// ```
+/// use rvm::watch::{Watch, Watcher};
+/// use rvm::{asm, Machine};
+///
+/// let mut m = Machine::new(asm::assemble("push 1\npush 2\naddi\nhalt").unwrap());
+/// let mut w = Watcher::new(vec![Watch::parse("top > 2", |_| Err(String::new())).unwrap()]);
+/// w.reset(&m);
+///
+/// let trigger = loop {
+///     m.step().unwrap();
+///     if let Some(t) = w.check(&m) {
+///         break t;
+///     }
+/// };
+/// assert_eq!(trigger.step, 3);
+/// ```
+#[derive(Default)]
+pub struct Watcher {
+    armed: Vec<Armed>,
+    step: u64,
+}
+
+impl Watcher {
+    pub fn new(watches: Vec<Watch>) -> Self {
+        let mut w = Self::default();
+        for watch in watches {
+            w.add(watch);
+        }
+
+        w
+    }
+
+    pub fn add(&mut self, watch: Watch) {
+        self.armed.push(Armed {
+            watch,
+            seen: None,
+            held: false,
+            hits: 0,
+        });
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watch> {
+        (index < self.armed.len()).then(|| self.armed.remove(index).watch)
+    }
+
+    pub fn watches(&self) -> impl Iterator<Item = &Watch> {
+        self.armed.iter().map(|a| &a.watch)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.armed.is_empty()
+    }
+
+    /// Starts over from `m`'s current state: the step count goes back to 0,
+    /// watched slots take their current values and a condition that already
+    /// holds has to turn false before it can fire. Nothing fires here, and
+    /// being at an `ip` already is not a hit; only steps arrive anywhere.
+    pub fn reset(&mut self, m: &Machine) {
+        self.step = 0;
+        for a in self.armed.iter_mut() {
+            a.seen = match a.watch {
+                Watch::Slot(i) => m.stack().get(i).copied(),
+                _ => None,
+            };
+            a.held = matches!(a.watch, Watch::When(c) if c.holds(m));
+            a.hits = 0;
+        }
+    }
+
+    /// Call after every step; returns the first watch that fired.
+    pub fn check(&mut self, m: &Machine) -> Option<Trigger> {
+        self.step += 1;
+        self.observe(m)
+    }
+
+    fn observe(&mut self, m: &Machine) -> Option<Trigger> {
+        let mut fired = None;
+
+        // Every watch sees every step, so slot values and hit counts stay
+        // current even when an earlier one fires.
+        for a in self.armed.iter_mut() {
+            let reason = match a.watch {
+                Watch::Slot(i) => {
+                    let now = m.stack().get(i).copied();
+                    let before = std::mem::replace(&mut a.seen, now);
+                    let changed = match (before, now) {
+                        (Some(Word::Float(x)), Some(Word::Float(y))) => x.to_bits() != y.to_bits(),
+                        _ => before != now,
+                    };
+
+                    changed.then(|| {
+                        let show = |w: Option<Word>| w.map_or("-".to_string(), |w| w.to_string());
+                        format!("stack[{i}] changed from {} to {}", show(before), show(now))
+                    })
+                }
+                // Only the step that makes it true fires, not every one after.
+                Watch::When(c) => {
+                    let held = std::mem::replace(&mut a.held, c.holds(m));
+                    (a.held && !held).then(|| {
+                        let value = c.lhs.read(m).map_or("-".to_string(), |w| w.to_string());
+                        format!("{c} ({} = {value})", c.lhs)
+                    })
+                }
+                Watch::Hits { ip, count } => {
+                    if m.ip() == ip {
+                        a.hits += 1;
+                    }
+                    (m.ip() == ip && a.hits == count)
+                        .then(|| format!("reached {ip} for the {} time", ordinal(count)))
+                }
+            };
+
+            if fired.is_none() {
+                fired = reason.map(|reason| Trigger {
+                    step: self.step,
+                    ip: m.ip(),
+                    reason,
+                });
+            }
+        }
+
+        fired
+    }
+}
+
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+
+    format!("{n}{suffix}")
+}
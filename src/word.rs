@@ -0,0 +1,88 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Word {
+    Float(f64),
+    Boolean(bool),
+    Int(i64),
+    // Usize(usize),
+}
+
+/// The type tag of a `Word`, without its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Float,
+    Boolean,
+    Int,
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Float => write!(f, "float"),
+            Kind::Boolean => write!(f, "bool"),
+            Kind::Int => write!(f, "int"),
+        }
+    }
+}
+
+impl Word {
+    pub fn kind(&self) -> Kind {
+        match self {
+            Word::Float(_) => Kind::Float,
+            Word::Boolean(_) => Kind::Boolean,
+            Word::Int(_) => Kind::Int,
+        }
+    }
+
+    pub fn is_true(&self) -> bool {
+        match self {
+            Word::Float(x) => *x > 0.0,
+            Word::Int(x) => *x > 0,
+            // Word::Usize(x) => *x > 0,
+            Word::Boolean(x) => *x,
+        }
+    }
+}
+
+impl Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Word::Float(x) => write!(f, "{:?}", x),
+            Word::Int(x) => write!(f, "{}", x),
+            // Word::Usize(x) => write!(f, "{}", x),
+            Word::Boolean(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl TryFrom<&str> for Word {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "true" => Ok(Word::Boolean(true)),
+            "false" => Ok(Word::Boolean(false)),
+            _ if s.contains('.') => s.parse::<f64>().map(Word::Float).map_err(|_| invalid(s)),
+            _ => match s.parse::<i64>() {
+                Ok(v) => Ok(Word::Int(v)),
+                // Digits alone are an int, even one too large for an `i64`.
+                Err(_) if is_int_syntax(s) => Err(format!("`{s}` is out of range for int")),
+                // `1e300`, `inf` and `NaN` are written without a '.'
+                Err(_) => s.parse::<f64>().map(Word::Float).map_err(|_| invalid(s)),
+            },
+        }
+    }
+}
+
+/// An optional sign followed by decimal digits.
+fn is_int_syntax(s: &str) -> bool {
+    let digits = s.strip_prefix(['-', '+']).unwrap_or(s);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn invalid(s: &str) -> String {
+    format!("`{s}` is not a valid int, float or boolean")
+}
@@ -0,0 +1,470 @@
+use rvm::{asm, Ins, Machine, RunOutcome, Word};
+
+#[test]
+fn collects_every_error_with_its_span() {
+    let src = "push 1\n\tpushh 2\npush 1.2.3 # bad\ndup x\nhalt\n";
+
+    let errors = asm::assemble_named("bad.vm", src).unwrap_err();
+
+    let spans = errors
+        .iter()
+        .map(|e| (e.line, e.col, e.token.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(spans, [(2, 2, "pushh"), (3, 6, "1.2.3"), (4, 5, "x")]);
+
+    assert_eq!(
+        errors[1].to_string(),
+        "error: `1.2.3` is not a valid int, float or boolean\n \
+         --> bad.vm:3:6\n  \
+         |\n\
+         3 | push 1.2.3 # bad\n  \
+         |      ^^^^^"
+    );
+}
+
+#[test]
+fn e_vm_labels_resolve_to_instruction_indices() {
+    let src = std::fs::read_to_string("e.vm").unwrap();
+    let (prog, info) = asm::assemble_with_debug_info("e.vm", &src).unwrap();
+
+    assert_eq!(info.labels["loop"], 3);
+    assert_eq!(prog[3], Ins::Push(Word::Float(1.0)));
+    assert_eq!(prog[prog.len() - 2], Ins::JumpIf(3));
+
+    let mut m = Machine::new(prog);
+    assert_eq!(m.run(10_000), RunOutcome::Halted);
+    match m.stack() {
+        [_, _, Word::Float(sum)] => assert!((sum - std::f64::consts::E).abs() < 1e-12),
+        stack => panic!("unexpected stack {stack:?}"),
+    }
+}
+
+#[test]
+fn extra_labels_and_numeric_jumpif_do_not_shift_e_vm() {
+    let src = std::fs::read_to_string("e.vm").unwrap();
+    let prog = asm::assemble(&src).unwrap();
+
+    // Labels ahead of `loop` used to push it past its instruction, and a
+    // numeric `jumpif` used to become an unconditional `jump`.
+    let edited = format!("start:\n{}", src.replace("jumpif loop", "jumpif 3\ndone:"));
+    let (edited, info) = asm::assemble_with_debug_info("e.vm", &edited).unwrap();
+
+    assert_eq!(edited, prog);
+    assert_eq!(info.labels["start"], 0);
+    assert_eq!(info.labels["loop"], 3);
+    assert_eq!(info.labels["done"], prog.len() - 1);
+}
+
+#[test]
+fn labels_can_be_used_before_they_are_defined() {
+    let src = "jump end\nstart:\npush 1\nend:\njumpif start\nhalt";
+
+    let prog = asm::assemble(src).unwrap();
+
+    assert_eq!(
+        prog,
+        [
+            Ins::Jump(2),
+            Ins::Push(Word::Int(1)),
+            Ins::JumpIf(1),
+            Ins::Halt
+        ]
+    );
+}
+
+#[test]
+fn duplicate_and_undefined_labels_are_reported() {
+    let src = "a:\npush 1\njump b\na:\nhalt\n";
+
+    let errors = asm::assemble_named("labels.vm", src).unwrap_err();
+
+    let found = errors
+        .iter()
+        .map(|e| (e.line, e.msg.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            (3, "undefined label `b` for `jump`"),
+            (4, "label `a` is already defined on line 1"),
+        ]
+    );
+}
+
+#[test]
+fn constants_and_expressions_fold_at_assembly_time() {
+    let src = "\
+.equ LIMIT 100.0
+.define DEPTH 3
+.equ NEXT end-1
+push LIMIT*2
+push -(DEPTH+1)%3
+dup DEPTH-1
+jumpif loop+1
+loop:
+jump NEXT
+end:
+halt
+";
+
+    let prog = asm::assemble(src).unwrap();
+
+    assert_eq!(
+        prog,
+        [
+            Ins::Push(Word::Float(200.0)),
+            Ins::Push(Word::Int(-1)),
+            Ins::Dup(2),
+            Ins::JumpIf(5),
+            Ins::Jump(4),
+            Ins::Halt,
+        ]
+    );
+}
+
+#[test]
+fn bad_constants_are_reported() {
+    let src = "\
+.equ A B
+.equ B 1
+.equ B 2
+.equ true 1
+.org 4
+push A/0
+dup B-2
+swap 1.5*
+";
+
+    let errors = asm::assemble_named("consts.vm", src).unwrap_err();
+
+    let found = errors
+        .iter()
+        .map(|e| (e.line, e.msg.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            (1, "undefined name `B`"),
+            (3, "constant `B` is already defined on line 2"),
+            (4, "`true` is not a valid constant name"),
+            (5, "unknown directive `.org`"),
+            (6, "undefined constant `A` for `push`"),
+            (7, "`dup` expects an index, found `B-2` = -1"),
+            (8, "`1.5*` is incomplete"),
+        ]
+    );
+}
+
+#[test]
+fn macros_expand_with_arguments_and_their_own_labels() {
+    let src = "\
+.macro next a b   # fib step
+    dup a
+    dup b
+    addi
+.endm
+.macro countdown n
+    push n
+top:
+    push 1
+    subi
+    dup 0
+    jumpif top
+.endm
+push 0
+push 1
+next 1 1
+countdown 2
+countdown 3
+halt
+";
+
+    let (prog, info) = asm::assemble_with_debug_info("macro.vm", src).unwrap();
+
+    let body = |top| {
+        [
+            Ins::Push(Word::Int(1)),
+            Ins::SubI,
+            Ins::Dup(0),
+            Ins::JumpIf(top),
+        ]
+    };
+    let mut expected = vec![
+        Ins::Push(Word::Int(0)),
+        Ins::Push(Word::Int(1)),
+        Ins::Dup(1),
+        Ins::Dup(1),
+        Ins::AddI,
+        Ins::Push(Word::Int(2)),
+    ];
+    expected.extend(body(6));
+    expected.push(Ins::Push(Word::Int(3)));
+    expected.extend(body(11));
+    expected.push(Ins::Halt);
+    assert_eq!(prog, expected);
+
+    // Expanded instructions sit on the line of the call.
+    assert_eq!(info.lines[2..5], [16, 16, 16]);
+    assert_eq!(info.lines[10..15], [18; 5]);
+}
+
+#[test]
+fn errors_in_macro_bodies_point_at_the_call_too() {
+    let src = ".macro twice x\n  push x\n  pushh x\n.endm\nhalt\ntwice 4\n";
+
+    let errors = asm::assemble_named("m.vm", src).unwrap_err();
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].to_string(),
+        "error: unknown instruction `pushh`\n \
+         --> m.vm:3:3\n  \
+         |\n\
+         3 |   pushh 4\n  \
+         |   ^^^^^\n\
+         note: in this expansion of macro `twice`\n \
+         --> m.vm:6:1\n  \
+         |\n\
+         6 | twice 4\n  \
+         | ^^^^^"
+    );
+}
+
+#[test]
+fn bad_macros_are_reported() {
+    let src = "\
+.macro forever
+  forever
+.endm
+.macro pair a a
+.endm
+.macro one x
+.endm
+forever
+one
+.endm
+.macro open
+";
+
+    let errors = asm::assemble_named("bad.vm", src).unwrap_err();
+
+    let found = errors
+        .iter()
+        .map(|e| {
+            let notes = std::iter::successors(e.note.as_deref(), |n| n.note.as_deref());
+            (e.line, e.msg.as_str(), notes.count())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            (2, "macro `forever` nests more than 64 calls deep", 2),
+            (4, "parameter `a` is repeated", 0),
+            (9, "macro `one` expects 1 argument(s), found 0", 0),
+            (10, "`.endm` without a `.macro`", 0),
+            (11, "`.macro` has no matching `.endm`", 0),
+        ]
+    );
+}
+
+/// Writes `files` to a fresh directory and returns its path.
+fn write_files(test: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("rvm-{test}-{}", std::process::id()));
+    for (name, src) in files {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, src).unwrap();
+    }
+    dir
+}
+
+fn assemble_file(path: &std::path::Path) -> Result<(Vec<Ins>, asm::DebugInfo), Vec<asm::AsmError>> {
+    let src = std::fs::read_to_string(path).unwrap();
+    asm::assemble_with_debug_info(path.to_str().unwrap(), &src)
+}
+
+#[test]
+fn includes_paste_files_in_place() {
+    let dir = write_files(
+        "include",
+        &[
+            ("main.vm", "push 1\n.include \"lib/two.vm\"\nhalt\n"),
+            ("lib/two.vm", ".include \"one.vm\"\n.include \"one.vm\"\n"),
+            ("lib/one.vm", "\n# one\npush 1\naddi\n"),
+        ],
+    );
+
+    let (prog, info) = assemble_file(&dir.join("main.vm")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        prog,
+        [
+            Ins::Push(Word::Int(1)),
+            Ins::Push(Word::Int(1)),
+            Ins::AddI,
+            Ins::Push(Word::Int(1)),
+            Ins::AddI,
+            Ins::Halt
+        ]
+    );
+    assert_eq!(info.lines, [1, 2, 2, 2, 2, 3]);
+}
+
+#[test]
+fn errors_in_included_files_point_at_the_include_too() {
+    let dir = write_files("include-error", &[("lib.vm", "pushh 1\n")]);
+    let main = dir.join("main.vm");
+    std::fs::write(&main, "push 1\n.include \"lib.vm\"\n").unwrap();
+
+    let errors = assemble_file(&main).unwrap_err();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].file, dir.join("lib.vm").display().to_string());
+    let note = errors[0].note.as_deref().unwrap();
+    assert_eq!(note.msg, "in the file included from here");
+    assert_eq!((note.file.as_str(), note.line), (main.to_str().unwrap(), 2));
+}
+
+#[test]
+fn modules_keep_their_labels_to_themselves() {
+    let dir = write_files(
+        "import",
+        &[
+            (
+                "main.vm",
+                ".import square TWO from \"math.vm\"\npush TWO\ncall square\nhalt\nhelper:\nhalt\n",
+            ),
+            (
+                "math.vm",
+                ".export square TWO\n.equ TWO 2\nsquare:\n  dup 0\n  muli\n  ret\nhelper:\n  ret\n",
+            ),
+        ],
+    );
+
+    let (prog, info) = assemble_file(&dir.join("main.vm")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(prog[1], Ins::Call(4));
+    assert_eq!(info.labels["helper"], 3);
+    assert_eq!(info.labels["math.square"], 4);
+    assert_eq!(info.labels["math.helper"], 7);
+    // Imported code sits on the line of the `.import`.
+    assert_eq!(info.lines, [2, 3, 4, 6, 1, 1, 1, 1]);
+
+    let mut m = Machine::new(prog);
+    assert_eq!(m.run(100), RunOutcome::Halted);
+    assert_eq!(m.stack(), [Word::Int(4)]);
+}
+
+#[test]
+fn bad_imports_and_cycles_are_reported() {
+    let dir = write_files(
+        "import-errors",
+        &[
+            (
+                "main.vm",
+                ".import hidden from \"lib.vm\"\n.import a from \"a.vm\"\n.include \"self.vm\"\nhalt\n",
+            ),
+            ("lib.vm", ".export nothing\nhidden:\nret\n"),
+            ("a.vm", ".import b from \"b.vm\"\n.export a\na:\nret\n"),
+            ("b.vm", ".import a from \"a.vm\"\n.export b\nb:\nret\n"),
+            ("self.vm", ".include \"self.vm\"\n"),
+        ],
+    );
+
+    let errors = assemble_file(&dir.join("main.vm")).unwrap_err();
+
+    let found = errors
+        .iter()
+        .map(|e| {
+            let file = std::path::Path::new(&e.file).file_name().unwrap();
+            let msg = e.msg.replace(&format!("{}/", dir.display()), "");
+            (file.to_str().unwrap().to_string(), e.line, msg)
+        })
+        .collect::<Vec<_>>();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let expect = |file: &str, line, msg: &str| (file.to_string(), line, msg.to_string());
+    assert_eq!(
+        found,
+        [
+            expect("main.vm", 1, "`lib.vm` does not export `hidden`"),
+            expect("b.vm", 1, "import cycle: a.vm -> b.vm -> a.vm"),
+            expect("lib.vm", 1, "cannot export `nothing`, it is not defined"),
+            expect("self.vm", 1, "include cycle: self.vm -> self.vm"),
+        ]
+    );
+}
+
+#[test]
+fn labels_must_be_names() {
+    let src = "inf:\nNaN:\n1x:\ndup:\nok.1:\nhalt\n";
+
+    let errors = asm::assemble_named("labels.vm", src).unwrap_err();
+
+    let found = errors
+        .iter()
+        .map(|e| (e.line, e.msg.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            (1, "`inf` is not a valid label name"),
+            (2, "`NaN` is not a valid label name"),
+            (3, "`1x` is not a valid label name"),
+            (4, "`dup` is not a valid label name"),
+        ]
+    );
+}
+
+#[test]
+fn ints_too_large_for_i64_are_errors_not_floats() {
+    let errors =
+        asm::assemble("push 9223372036854775808\npush -9223372036854775809\n").unwrap_err();
+    let msgs = errors.iter().map(|e| e.msg.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        msgs,
+        [
+            "`9223372036854775808` is out of range for int",
+            "`9223372036854775809` is out of range for int",
+        ]
+    );
+
+    let prog = asm::assemble("push 1e300\npush -9223372036854775808\n").unwrap();
+    assert_eq!(
+        prog,
+        [
+            Ins::Push(Word::Float(1e300)),
+            Ins::Push(Word::Int(i64::MIN))
+        ]
+    );
+}
+
+#[test]
+fn labels_shaped_like_macro_expansions_are_rejected() {
+    let src = "\
+.macro spin
+top:
+    jump top
+.endm
+spin
+top.spin.1:
+    jump top.spin.1
+";
+
+    let errors = asm::assemble_named("spin.vm", src).unwrap_err();
+
+    let found = errors
+        .iter()
+        .map(|e| (e.line, e.msg.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [(
+            6,
+            "`top.spin.1` is not a valid label name, macro expansions name their labels `label.macro.n`"
+        )]
+    );
+}
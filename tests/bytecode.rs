@@ -0,0 +1,35 @@
+use rvm::bytecode::{self, BytecodeErr};
+use rvm::{asm, Ins, Word};
+
+#[test]
+fn encode_decode_round_trip() {
+    let mut prog = asm::read_source_file("e.vm").unwrap();
+    prog.push(Ins::Push(Word::Boolean(true)));
+    prog.push(Ins::Push(Word::Int(-7)));
+
+    let bytes = bytecode::encode(&prog).unwrap();
+
+    assert!(bytecode::is_bytecode(&bytes));
+    assert_eq!(bytecode::decode(&bytes).unwrap(), prog);
+}
+
+#[test]
+fn decode_rejects_corrupt_bytes() {
+    let prog = asm::read_source_file("basic.vm").unwrap();
+    let mut bytes = bytecode::encode(&prog).unwrap();
+
+    assert!(matches!(
+        bytecode::decode(b"push 1\nhalt\n"),
+        Err(BytecodeErr::BadMagic)
+    ));
+    assert!(matches!(
+        bytecode::decode(&bytes[..bytes.len() - 1]),
+        Err(BytecodeErr::ChecksumMismatch { .. })
+    ));
+
+    bytes[12] ^= 0xff;
+    assert!(matches!(
+        bytecode::decode(&bytes),
+        Err(BytecodeErr::ChecksumMismatch { .. })
+    ));
+}
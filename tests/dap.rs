@@ -0,0 +1,275 @@
+use rvm::dap;
+use rvm::json::{self, Value};
+use std::io::{self, BufReader};
+use std::thread;
+
+/// Sends `(command, arguments)` requests and returns every message sent back.
+/// Like an editor, it waits for each answer before the next request, and
+/// after one that runs the program, for the program to stop.
+fn session(requests: &[(&str, &str)]) -> Vec<Value> {
+    exchange(requests, true)
+}
+
+fn exchange(requests: &[(&str, &str)], wait_for_stops: bool) -> Vec<Value> {
+    let (input, mut to_adapter) = io::pipe().unwrap();
+    let (from_adapter, out) = io::pipe().unwrap();
+    let adapter = thread::spawn(move || dap::serve(BufReader::new(input), out));
+    let mut from_adapter = BufReader::new(from_adapter);
+
+    let mut msgs = Vec::new();
+    let mut until = |msgs: &mut Vec<Value>, done: &dyn Fn(&Value) -> bool| loop {
+        let msg = dap::read_message(&mut from_adapter).unwrap().unwrap();
+        msgs.push(msg);
+        if done(&msgs[msgs.len() - 1]) {
+            return msgs[msgs.len() - 1].clone();
+        }
+    };
+
+    for (seq, (command, args)) in requests.iter().enumerate() {
+        let req = format!(
+            r#"{{"seq":{},"type":"request","command":"{command}","arguments":{args}}}"#,
+            seq + 1
+        );
+        dap::write_message(&mut to_adapter, &json::parse(&req).unwrap()).unwrap();
+
+        let response = until(&mut msgs, &|m| {
+            m.get("request_seq").as_usize() == Some(seq + 1)
+        });
+        let runs = matches!(
+            *command,
+            "configurationDone" | "continue" | "next" | "stepIn" | "stepOut"
+        );
+        if wait_for_stops && runs && response.get("success") == &Value::Bool(true) {
+            until(&mut msgs, &|m| {
+                matches!(m.get("event").as_str(), Some("stopped" | "exited"))
+            });
+        }
+    }
+
+    drop(to_adapter);
+    adapter.join().unwrap().unwrap();
+    while let Some(msg) = dap::read_message(&mut from_adapter).unwrap() {
+        msgs.push(msg);
+    }
+
+    msgs
+}
+
+/// The bodies of every response to `command`, in order.
+fn responses<'a>(msgs: &'a [Value], command: &str) -> Vec<&'a Value> {
+    msgs.iter()
+        .filter(|m| m.get("type").as_str() == Some("response"))
+        .filter(|m| m.get("command").as_str() == Some(command))
+        .inspect(|m| assert_eq!(m.get("success"), &Value::Bool(true), "{m}"))
+        .map(|m| m.get("body"))
+        .collect()
+}
+
+/// The `reason` of every stopped event, or the name of any other event.
+fn events(msgs: &[Value]) -> Vec<&str> {
+    msgs.iter()
+        .filter(|m| m.get("type").as_str() == Some("event"))
+        .filter_map(|m| match m.get("event").as_str() {
+            Some("stopped") => m.get("body").get("reason").as_str(),
+            event => event,
+        })
+        .collect()
+}
+
+fn stack(vars: &Value) -> Vec<String> {
+    vars.get("variables")
+        .as_array()
+        .iter()
+        .map(|v| {
+            let field = |k| v.get(k).as_str().unwrap();
+            format!("{} = {}", field("name"), field("value"))
+        })
+        .collect()
+}
+
+#[test]
+fn breakpoints_map_source_lines_to_instructions() {
+    let msgs = session(&[
+        ("initialize", r#"{"adapterID":"rvm"}"#),
+        ("launch", r#"{"program":"feb.vm"}"#),
+        (
+            "setBreakpoints",
+            r#"{"source":{"path":"feb.vm"},"breakpoints":[{"line":5},{"line":40}]}"#,
+        ),
+        ("configurationDone", "{}"),
+        ("stackTrace", r#"{"threadId":1}"#),
+        ("variables", r#"{"variablesReference":1}"#),
+        ("continue", r#"{"threadId":1}"#),
+        ("variables", r#"{"variablesReference":1}"#),
+        ("disconnect", "{}"),
+    ]);
+
+    // The label on line 5 has no instruction, so the breakpoint moves down.
+    let bps = responses(&msgs, "setBreakpoints")[0].get("breakpoints");
+    assert_eq!(bps.as_array()[0].get("line").as_usize(), Some(6));
+    assert_eq!(bps.as_array()[1].get("verified"), &Value::Bool(false));
+
+    assert_eq!(events(&msgs), ["initialized", "breakpoint", "breakpoint"]);
+
+    let frames = responses(&msgs, "stackTrace")[0].get("stackFrames");
+    assert_eq!(frames.as_array().len(), 1);
+    assert_eq!(frames.as_array()[0].get("line").as_usize(), Some(6));
+    assert_eq!(frames.as_array()[0].get("name").as_str(), Some("main"));
+
+    let vars = responses(&msgs, "variables");
+    assert_eq!(stack(vars[0]), ["stack[0] = 0", "stack[1] = 1"]);
+    assert_eq!(stack(vars[1]).len(), 3);
+}
+
+#[test]
+fn stepping_in_over_and_out_of_calls() {
+    let path = std::env::temp_dir().join(format!("rvm-dap-{}.vm", std::process::id()));
+    let src = "push 6\ncall f\nhalt\nf:\nenter 2\nload_arg 0\nstore_local 1\nleave\nret";
+    std::fs::write(&path, src).unwrap();
+    let launch = format!(
+        r#"{{"program":{},"stopOnEntry":true}}"#,
+        Value::from(path.to_str().unwrap())
+    );
+
+    let msgs = session(&[
+        ("initialize", "{}"),
+        ("launch", &launch),
+        ("configurationDone", "{}"),
+        ("next", "{}"),
+        ("stepIn", "{}"),
+        ("stackTrace", "{}"),
+        ("next", "{}"),
+        ("next", "{}"),
+        ("next", "{}"),
+        ("scopes", r#"{"frameId":1}"#),
+        ("scopes", r#"{"frameId":0}"#),
+        ("variables", r#"{"variablesReference":2}"#),
+        ("stepOut", "{}"),
+        ("stackTrace", "{}"),
+        ("next", "{}"),
+    ]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        events(&msgs),
+        [
+            "initialized",
+            "entry",
+            "step",
+            "step",
+            "step",
+            "step",
+            "step",
+            "step",
+            "terminated",
+            "exited"
+        ]
+    );
+
+    let traces = responses(&msgs, "stackTrace");
+    let frames = traces[0].get("stackFrames").as_array();
+    let names = frames
+        .iter()
+        .map(|f| f.get("name").as_str().unwrap())
+        .collect::<Vec<_>>();
+    let lines = frames
+        .iter()
+        .map(|f| f.get("line").as_usize().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["f", "main"]);
+    assert_eq!(lines, [5, 2]);
+
+    let frames = traces[1].get("stackFrames").as_array();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].get("line").as_usize(), Some(3));
+
+    let scopes = responses(&msgs, "scopes");
+    assert_eq!(scopes[0].get("scopes").as_array().len(), 2);
+    assert_eq!(scopes[1].get("scopes").as_array().len(), 1);
+
+    let locals = responses(&msgs, "variables")[0];
+    assert_eq!(stack(locals), ["local[0] = 0", "local[1] = 6"]);
+}
+
+#[test]
+fn requests_before_launch_fail() {
+    let msgs = session(&[("initialize", "{}"), ("stackTrace", "{}"), ("launch", "{}")]);
+
+    let failed = msgs
+        .iter()
+        .filter(|m| m.get("success") == &Value::Bool(false))
+        .map(|m| m.get("message").as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        failed,
+        [
+            "no program has been launched",
+            "launch needs a `program` to debug"
+        ]
+    );
+}
+
+#[test]
+fn json_round_trips() {
+    let src = r#"{"a":[1,-2.5,true,null],"b":"q\"\\\né😀","c":{}}"#;
+    let v = json::parse(src).unwrap();
+
+    assert_eq!(v.get("b").as_str(), Some("q\"\\\né😀"));
+    assert_eq!(json::parse(&v.to_string()).unwrap(), v);
+    assert!(json::parse("[1,]").is_err());
+    assert!(json::parse("{} x").is_err());
+}
+
+#[test]
+fn breakpoints_in_other_files_leave_the_program_alone() {
+    let msgs = session(&[
+        ("initialize", "{}"),
+        ("launch", r#"{"program":"feb.vm"}"#),
+        (
+            "setBreakpoints",
+            r#"{"source":{"path":"feb.vm"},"breakpoints":[{"line":6}]}"#,
+        ),
+        (
+            "setBreakpoints",
+            r#"{"source":{"path":"lib.vm"},"breakpoints":[{"line":2}]}"#,
+        ),
+        ("configurationDone", "{}"),
+        ("stackTrace", "{}"),
+        ("disconnect", "{}"),
+    ]);
+
+    let bps = responses(&msgs, "setBreakpoints");
+    assert_eq!(
+        bps[1].get("breakpoints").as_array()[0].get("verified"),
+        &Value::Bool(false)
+    );
+
+    // Still stops on line 6 of the program, not line 2.
+    assert_eq!(events(&msgs), ["initialized", "breakpoint"]);
+    let frames = responses(&msgs, "stackTrace")[0].get("stackFrames");
+    assert_eq!(frames.as_array()[0].get("line").as_usize(), Some(6));
+}
+
+#[test]
+fn programs_that_never_stop_can_be_paused_and_left() {
+    let msgs = exchange(
+        &[
+            ("initialize", "{}"),
+            ("launch", r#"{"program":"count.vm"}"#),
+            ("configurationDone", "{}"),
+            ("threads", "{}"),
+            ("pause", r#"{"threadId":1}"#),
+            ("stackTrace", r#"{"threadId":1}"#),
+            ("continue", r#"{"threadId":1}"#),
+            ("disconnect", "{}"),
+        ],
+        false,
+    );
+
+    // Requests are answered while it runs, and only `pause` stops it.
+    assert_eq!(responses(&msgs, "threads").len(), 1);
+    assert_eq!(events(&msgs), ["initialized", "pause"]);
+    let frames = responses(&msgs, "stackTrace")[0].get("stackFrames");
+    assert_eq!(frames.as_array().len(), 1);
+    assert_eq!(responses(&msgs, "disconnect").len(), 1);
+}
@@ -0,0 +1,129 @@
+use rvm::asm;
+use rvm::debug::{Debugger, Stop};
+use rvm::{Machine, MachineErr, Word};
+
+fn debugger(src: &str) -> Debugger {
+    let (prog, info) = asm::assemble_with_debug_info("<source>", src).unwrap();
+    Debugger::new(Machine::new(prog), info)
+}
+
+fn session(dbg: &mut Debugger, commands: &str) -> String {
+    let mut out = Vec::new();
+    dbg.repl(commands.as_bytes(), &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn breakpoints_stop_continue_by_label_and_index() {
+    let src = std::fs::read_to_string("feb.vm").unwrap();
+    let (prog, info) = asm::assemble_with_debug_info("feb.vm", &src).unwrap();
+    let mut dbg = Debugger::new(Machine::new(prog), info);
+
+    let out = session(
+        &mut dbg,
+        "break loop\ncontinue\ncontinue\nprint stack\nquit\n",
+    );
+
+    assert!(out.contains("Breakpoint at 2 (loop, line 6)"));
+    assert!(out.contains("stack[2] = 1"));
+    assert_eq!(dbg.machine().ip(), 2);
+
+    dbg.clear_breakpoints();
+    dbg.add_breakpoint(dbg.location("5").unwrap());
+    assert_eq!(dbg.resume(), Stop::Breakpoint(5));
+}
+
+#[test]
+fn set_patches_the_stack_and_restart_undoes_it() {
+    let mut dbg = debugger("push 1\npush 2\naddi\nhalt");
+
+    let out = session(
+        &mut dbg,
+        "step 2\nset stack[0] = 40\ncontinue\nprint stack\n",
+    );
+    assert!(out.contains("stack[1] = 2"));
+    assert!(out.contains("Program halted"));
+    assert_eq!(dbg.machine().stack(), &[Word::Int(42)]);
+
+    let out = session(&mut dbg, "restart\nprint ip\nset stack[0] = 1\n");
+    assert!(out.contains("ip = 0"));
+    assert!(out.contains("ERROR: stack[0] is not live, sp is 0"));
+}
+
+#[test]
+fn stepping_stops_at_errors() {
+    let mut dbg = debugger("push 1\naddi\nhalt");
+
+    assert_eq!(dbg.step(5), Stop::Trapped(MachineErr::StackUnderflow));
+    assert_eq!(dbg.machine().ip(), 1);
+}
+
+#[test]
+fn watches_stop_continue() {
+    let src = std::fs::read_to_string("feb.vm").unwrap();
+    let (prog, info) = asm::assemble_with_debug_info("feb.vm", &src).unwrap();
+    let mut dbg = Debugger::new(Machine::new(prog), info);
+
+    let out = session(
+        &mut dbg,
+        "watch top > 4\ncontinue\nwatch\nunwatch 0\nunwatch 0\n",
+    );
+
+    assert!(out.contains("Watching top > 4"));
+    assert!(out.contains("Watch triggered at step 17, ip 5: top > 4 (top = 5)"));
+    assert!(out.contains("Watch 0: top > 4"));
+    assert!(out.contains("ERROR: no watch `0`"));
+}
+
+#[test]
+fn history_steps_back_to_breakpoints_and_step_numbers() {
+    let src = "push 1\nloop:\npush 1\naddi\njump loop";
+    let (prog, info) = asm::assemble_with_debug_info("<source>", src).unwrap();
+    let mut dbg = Debugger::new(Machine::new(prog), info).with_history(8);
+
+    assert_eq!(dbg.step(30), Stop::Stepped);
+    assert_eq!(dbg.machine().stack(), &[Word::Int(11)]);
+    assert_eq!(dbg.oldest_step(), 22);
+
+    dbg.add_breakpoint(2);
+    assert_eq!(dbg.reverse_resume(), Stop::Breakpoint(2));
+    assert_eq!(dbg.steps(), 29);
+    assert_eq!(dbg.machine().stack(), &[Word::Int(10), Word::Int(1)]);
+
+    assert_eq!(dbg.goto(24), Ok(Stop::Stepped));
+    assert_eq!(dbg.machine().stack(), &[Word::Int(9)]);
+    assert!(dbg.goto(21).is_err());
+    assert_eq!(dbg.reverse_step(5), Stop::StartOfHistory);
+    assert_eq!(dbg.steps(), 22);
+
+    // Going forward again records a new timeline.
+    assert_eq!(dbg.goto(30), Ok(Stop::Stepped));
+    assert_eq!(dbg.machine().stack(), &[Word::Int(11)]);
+}
+
+#[test]
+fn trapped_steps_are_rolled_back() {
+    let mut dbg = debugger("push 1\nenter 0\nleave\nleave\nhalt").with_history(4);
+
+    assert_eq!(dbg.step(10), Stop::Trapped(MachineErr::NoFrame));
+    assert_eq!(dbg.steps(), 3);
+    assert_eq!(dbg.machine().ip(), 3);
+
+    let out = session(&mut dbg, "rs\nprint step\n");
+    assert!(out.contains("[step 2] => 2 (line 3): leave"));
+    assert!(out.contains("step = 2, history from step 0"));
+}
+
+#[test]
+fn conditions_fire_when_they_become_true_not_while_they_hold() {
+    let mut dbg = debugger("push 1\npush 2\npush 3\npop\npop\npush 4\nhalt");
+
+    let out = session(&mut dbg, "watch top > 1\ncontinue\ncontinue\ncontinue\n");
+
+    // True from step 2 to 4, false at 5 and true again at 6.
+    let fired = out.matches("Watch triggered").count();
+    assert_eq!(fired, 2);
+    assert!(out.contains("Watch triggered at step 2, ip 2: top > 1 (top = 2)"));
+    assert!(out.contains("Watch triggered at step 6, ip 6: top > 1 (top = 4)"));
+    assert!(out.contains("Program halted"));
+}
@@ -0,0 +1,45 @@
+use rvm::{asm, bytecode, disasm, Ins};
+
+const SAMPLES: [&str; 5] = ["e.vm", "basic.vm", "feb.vm", "febf.vm", "febi.vm"];
+
+#[test]
+fn disassembly_reassembles_to_same_program() {
+    for sample in SAMPLES {
+        let prog = asm::read_source_file(sample).unwrap();
+
+        let src = disasm::disassemble(&prog);
+
+        assert_eq!(asm::assemble(&src).unwrap(), prog, "{sample}:\n{src}");
+    }
+}
+
+#[test]
+fn bytecode_disassembles_to_same_program() {
+    for sample in SAMPLES {
+        let prog = asm::read_source_file(sample).unwrap();
+        let bytes = bytecode::encode(&prog).unwrap();
+
+        let src = disasm::disassemble(&bytecode::decode(&bytes).unwrap());
+
+        assert_eq!(asm::assemble(&src).unwrap(), prog, "{sample}:\n{src}");
+    }
+}
+
+#[test]
+fn jump_targets_get_labels() {
+    let prog = asm::read_source_file("feb.vm").unwrap();
+
+    let src = disasm::disassemble(&prog);
+
+    assert!(src.contains("L0:\n    dup 1\n"), "{src}");
+    assert!(src.contains("    jump L0\n"), "{src}");
+}
+
+#[test]
+fn targets_past_the_end_round_trip() {
+    let prog = [Ins::Jump(10), Ins::JumpIf(20), Ins::Call(4), Ins::Halt];
+
+    let src = disasm::disassemble(&prog);
+
+    assert_eq!(asm::assemble(&src).unwrap(), prog, "{src}");
+}
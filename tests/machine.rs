@@ -0,0 +1,506 @@
+use rvm::fuel::CostTable;
+use rvm::machine::{FloatMode, Overflow};
+use rvm::word::Kind;
+use rvm::{asm, Ins, Machine, MachineErr, RunOutcome, Word};
+use std::cell::Cell;
+use std::rc::Rc;
+
+const FUEL: u64 = 1000;
+
+#[test]
+fn runs_basic_vm_to_halt() {
+    let prog = asm::read_source_file("basic.vm").unwrap();
+    let mut m = Machine::new(prog);
+
+    assert_eq!(m.run(FUEL), RunOutcome::Halted);
+
+    assert!(m.is_halted());
+    assert_eq!(m.stack(), &[Word::Int(6)]);
+}
+
+#[test]
+fn running_out_of_fuel_can_be_resumed() {
+    let prog = asm::read_source_file("feb.vm").unwrap();
+    let mut m = Machine::builder().program(prog).max_stack_size(100).build();
+
+    assert_eq!(m.run(5), RunOutcome::OutOfFuel { remaining_ip: 5 });
+    assert!(!m.is_halted());
+    assert_eq!(m.fuel(), 0);
+    assert_eq!(m.stack(), &[Word::Int(0), Word::Int(1), Word::Int(1)]);
+
+    // One more trip round the loop.
+    assert_eq!(m.run(4), RunOutcome::OutOfFuel { remaining_ip: 5 });
+    assert_eq!(m.stack()[3], Word::Int(2));
+}
+
+#[test]
+fn cost_table_prices_instructions() {
+    let prog = asm::assemble("push 6\npush 3\ndivi\nhalt").unwrap();
+    let costs = CostTable::uniform(1).with(Ins::DivI, 10);
+    let mut m = Machine::builder().program(prog).cost_table(costs).build();
+
+    // Enough for both pushes, not for the division.
+    assert_eq!(m.run(11), RunOutcome::OutOfFuel { remaining_ip: 2 });
+    assert_eq!(m.fuel(), 9);
+
+    assert_eq!(m.run(2), RunOutcome::Halted);
+    assert_eq!(m.fuel(), 0);
+    assert_eq!(m.stack(), &[Word::Int(2)]);
+}
+
+#[test]
+fn running_off_the_end_is_a_trap() {
+    let mut m = Machine::new(vec![Ins::Push(Word::Int(1))]);
+
+    assert_eq!(
+        m.run(FUEL),
+        RunOutcome::Trapped(MachineErr::IpOutOfBounds(1))
+    );
+}
+
+#[test]
+fn stack_size_bounds_push() {
+    let prog = vec![Ins::Push(Word::Int(1)), Ins::Push(Word::Int(2)), Ins::Halt];
+    let mut m = Machine::builder().program(prog).stack_size(1).build();
+
+    assert_eq!(m.run(FUEL), RunOutcome::Trapped(MachineErr::StackOverflow));
+}
+
+#[test]
+fn on_step_hook_sees_every_step() {
+    let steps = Rc::new(Cell::new(0));
+    let seen = steps.clone();
+
+    let prog = asm::assemble("push 1\npush 2\naddi\nhalt").unwrap();
+    let mut m = Machine::builder()
+        .program(prog)
+        .on_step(move |_| seen.set(seen.get() + 1))
+        .build();
+
+    assert_eq!(m.run(FUEL), RunOutcome::Halted);
+    assert_eq!(steps.get(), 4);
+}
+
+#[test]
+fn on_step_hook_sees_the_instruction_just_executed() {
+    let seen = Rc::new(std::cell::RefCell::new(Vec::new()));
+    let log = seen.clone();
+
+    let prog = asm::assemble(
+        "loop:
+push 1
+pop
+jump loop",
+    )
+    .unwrap();
+    let mut m = Machine::builder()
+        .program(prog)
+        .on_step(move |m| {
+            log.borrow_mut().push(m.last_ip());
+            // Used to panic once a jump left `ip` at 0.
+            m.dump();
+        })
+        .build();
+
+    assert_eq!(m.last_ip(), None);
+    assert!(matches!(m.run(5), RunOutcome::OutOfFuel { .. }));
+    assert_eq!(
+        *seen.borrow(),
+        [Some(0), Some(1), Some(2), Some(0), Some(1)]
+    );
+}
+
+#[test]
+fn call_returns_to_caller() {
+    // square(3) + 1, with square as a subroutine at 5
+    let prog = vec![
+        Ins::Push(Word::Int(3)),
+        Ins::Call(5),
+        Ins::Push(Word::Int(1)),
+        Ins::AddI,
+        Ins::Halt,
+        Ins::Dup(0),
+        Ins::MulI,
+        Ins::Ret,
+    ];
+    let mut m = Machine::new(prog);
+
+    assert_eq!(m.run(FUEL), RunOutcome::Halted);
+
+    assert_eq!(m.stack(), &[Word::Int(10)]);
+    assert!(m.call_stack().is_empty());
+}
+
+#[test]
+fn call_stack_is_bounded() {
+    let mut m = Machine::builder()
+        .program(vec![Ins::Call(0)])
+        .call_stack_size(4)
+        .build();
+
+    assert_eq!(
+        m.run(FUEL),
+        RunOutcome::Trapped(MachineErr::CallStackOverflow)
+    );
+    assert_eq!(m.call_stack().len(), 4);
+
+    let mut m = Machine::new(vec![Ins::Ret]);
+    assert_eq!(
+        m.run(FUEL),
+        RunOutcome::Trapped(MachineErr::CallStackUnderflow)
+    );
+}
+
+#[test]
+fn frames_hold_locals_and_args() {
+    // f(a, b) = a * b + a, result written back over argument `a`
+    let prog = vec![
+        Ins::Push(Word::Int(6)),
+        Ins::Push(Word::Int(7)),
+        Ins::Call(5),
+        Ins::Pop,
+        Ins::Halt,
+        Ins::Enter(1),
+        Ins::LoadArg(0),
+        Ins::LoadArg(1),
+        Ins::MulI,
+        Ins::StoreLocal(0),
+        Ins::LoadLocal(0),
+        Ins::LoadArg(1),
+        Ins::AddI,
+        Ins::StoreArg(1),
+        Ins::Leave,
+        Ins::Ret,
+    ];
+    let mut m = Machine::new(prog);
+
+    assert_eq!(m.run(FUEL), RunOutcome::Halted);
+
+    assert_eq!(m.stack(), &[Word::Int(48)]);
+    assert!(m.frames().is_empty());
+}
+
+#[test]
+fn leave_below_its_frame_keeps_the_frame() {
+    let prog = vec![
+        Ins::Push(Word::Int(1)),
+        Ins::Enter(1),
+        Ins::Pop,
+        Ins::Pop,
+        Ins::Leave,
+    ];
+    let mut m = Machine::new(prog);
+
+    assert_eq!(m.run(FUEL), RunOutcome::Trapped(MachineErr::StackUnderflow));
+    assert_eq!(m.frames().len(), 1);
+    assert_eq!(m.ip(), 4);
+}
+
+#[test]
+fn frame_slots_are_bounds_checked() {
+    let run = |prog| Machine::new(prog).run(FUEL);
+
+    assert_eq!(
+        run(vec![Ins::LoadLocal(0)]),
+        RunOutcome::Trapped(MachineErr::NoFrame)
+    );
+    assert_eq!(
+        run(vec![Ins::Leave]),
+        RunOutcome::Trapped(MachineErr::NoFrame)
+    );
+    assert_eq!(
+        run(vec![Ins::Enter(2), Ins::LoadLocal(2)]),
+        RunOutcome::Trapped(MachineErr::LocalOutOfBounds(2))
+    );
+    assert_eq!(
+        run(vec![
+            Ins::Push(Word::Int(1)),
+            Ins::Enter(0),
+            Ins::LoadArg(1)
+        ]),
+        RunOutcome::Trapped(MachineErr::ArgOutOfBounds(1))
+    );
+}
+
+#[test]
+fn comparisons_take_operands_in_push_order() {
+    let top = |src: &str| {
+        let mut m = Machine::new(asm::assemble(src).unwrap());
+        assert_eq!(m.run(FUEL), RunOutcome::Halted);
+        m.stack()[m.sp() - 1]
+    };
+
+    assert_eq!(top("push 1\npush 2\nlti\nhalt"), Word::Boolean(true));
+    assert_eq!(top("push 1\npush 2\ngei\nhalt"), Word::Boolean(false));
+    assert_eq!(top("push 2.5\npush 2.5\nlef\nhalt"), Word::Boolean(true));
+    assert_eq!(
+        top("push 1.0\npush 2.0\ngtf\nnot\nhalt"),
+        Word::Boolean(true)
+    );
+    assert_eq!(top("push true\npush false\nxor\nhalt"), Word::Boolean(true));
+}
+
+#[test]
+fn comparisons_reject_mixed_operands() {
+    let mut m = Machine::new(asm::assemble("push 1\npush 2.0\neqi\nhalt").unwrap());
+
+    assert_eq!(
+        m.run(FUEL),
+        RunOutcome::Trapped(MachineErr::TypeMismatch {
+            ins: Ins::EqI,
+            expected: Kind::Int,
+            found: Kind::Float,
+        })
+    );
+    assert_eq!(m.sp(), 2);
+}
+
+#[test]
+fn bitwise_and_shift_ops() {
+    let top = |src: &str| {
+        let mut m = Machine::new(asm::assemble(src).unwrap());
+        assert_eq!(m.run(FUEL), RunOutcome::Halted);
+        m.stack()[m.sp() - 1]
+    };
+
+    assert_eq!(top("push 12\npush 10\nband\nhalt"), Word::Int(8));
+    assert_eq!(top("push 12\npush 10\nbor\nhalt"), Word::Int(14));
+    assert_eq!(top("push 12\npush 10\nbxor\nhalt"), Word::Int(6));
+    assert_eq!(top("push 0\nbnot\nhalt"), Word::Int(-1));
+    assert_eq!(top("push 1\npush 65\nshl\nhalt"), Word::Int(2));
+    assert_eq!(top("push -8\npush 1\nshr\nhalt"), Word::Int(-4));
+    assert_eq!(top("push -1\npush 60\nushr\nhalt"), Word::Int(15));
+    assert_eq!(top("push -7\npush 3\nmodi\nhalt"), Word::Int(2));
+    assert_eq!(top("push -7\npush 3\nremi\nhalt"), Word::Int(-1));
+    assert_eq!(top("push 5\nnegi\nhalt"), Word::Int(-5));
+    assert_eq!(top("push 2.5\nnegf\nhalt"), Word::Float(-2.5));
+}
+
+#[test]
+fn conversions_between_kinds() {
+    let top = |src: &str| {
+        let mut m = Machine::new(asm::assemble(src).unwrap());
+        assert_eq!(m.run(FUEL), RunOutcome::Halted);
+        m.stack()[m.sp() - 1]
+    };
+
+    assert_eq!(top("push 3\ni2f\nhalt"), Word::Float(3.0));
+    assert_eq!(top("push -2.5\nf2i\nhalt"), Word::Int(-2));
+    assert_eq!(top("push -2.5\nf2i round\nhalt"), Word::Int(-3));
+    assert_eq!(top("push -2.5\nf2i floor\nhalt"), Word::Int(-3));
+    assert_eq!(top("push 2.1\nf2i ceil\nhalt"), Word::Int(3));
+    assert_eq!(top("push true\nb2i\nhalt"), Word::Int(1));
+    assert_eq!(top("push 0\ni2b\nhalt"), Word::Boolean(false));
+
+    // an int counter feeding float arithmetic
+    assert_eq!(top("push 1.5\npush 2\ni2f\nmulf\nhalt"), Word::Float(3.0));
+}
+
+#[test]
+fn arithmetic_checks_operand_kinds() {
+    let run = |src: &str| Machine::new(asm::assemble(src).unwrap()).run(FUEL);
+
+    assert_eq!(
+        run("push 1.0\npush 2.0\naddi\nhalt"),
+        RunOutcome::Trapped(MachineErr::TypeMismatch {
+            ins: Ins::AddI,
+            expected: Kind::Int,
+            found: Kind::Float,
+        })
+    );
+    assert_eq!(
+        run("push 1.0\npush 2\nmulf\nhalt"),
+        RunOutcome::Trapped(MachineErr::TypeMismatch {
+            ins: Ins::MulF,
+            expected: Kind::Float,
+            found: Kind::Int,
+        })
+    );
+}
+
+#[test]
+fn integer_overflow_follows_mode() {
+    let run = |mode, src: &str| {
+        let prog = asm::assemble(src).unwrap();
+        let mut m = Machine::builder().program(prog).overflow(mode).build();
+        match m.run(FUEL) {
+            RunOutcome::Halted => Ok(m.stack()[m.sp() - 1]),
+            outcome => Err(outcome),
+        }
+    };
+    let max_plus_one = "push 9223372036854775807\npush 1\naddi\nhalt";
+
+    assert_eq!(
+        run(Overflow::Trap, max_plus_one),
+        Err(RunOutcome::Trapped(MachineErr::IntegerOverflow { ip: 2 }))
+    );
+    assert_eq!(
+        run(Overflow::Wrap, max_plus_one).unwrap(),
+        Word::Int(i64::MIN)
+    );
+    assert_eq!(
+        run(Overflow::Saturate, max_plus_one).unwrap(),
+        Word::Int(i64::MAX)
+    );
+
+    let min = "push -9223372036854775807\npush 1\nsubi\n";
+    assert_eq!(
+        run(Overflow::Saturate, &format!("{min}negi\nhalt")).unwrap(),
+        Word::Int(i64::MAX)
+    );
+    assert_eq!(
+        run(Overflow::Wrap, &format!("{min}push -1\ndivi\nhalt")).unwrap(),
+        Word::Int(i64::MIN)
+    );
+}
+
+#[test]
+fn division_by_zero_is_an_error() {
+    for op in ["divi", "modi", "remi"] {
+        let prog = asm::assemble(&format!("push 1\npush 0\n{op}\nhalt")).unwrap();
+        let mut m = Machine::builder()
+            .program(prog)
+            .overflow(Overflow::Wrap)
+            .build();
+
+        assert_eq!(
+            m.run(FUEL),
+            RunOutcome::Trapped(MachineErr::DivisionByZero { ip: 2 })
+        );
+    }
+}
+
+#[test]
+fn float_results_follow_mode() {
+    let run = |mode, trap, src: &str| {
+        let prog = asm::assemble(src).unwrap();
+        let mut m = Machine::builder()
+            .program(prog)
+            .float_mode(mode)
+            .trap_non_finite(trap)
+            .build();
+        match m.run(FUEL) {
+            RunOutcome::Halted => Ok(m.stack()[m.sp() - 1]),
+            outcome => Err(outcome),
+        }
+    };
+    let third = "push 1.0\npush 3.0\ndivf\nhalt";
+
+    assert_eq!(
+        run(FloatMode::Strict, false, third).unwrap(),
+        Word::Float(1.0 / 3.0)
+    );
+    assert_eq!(
+        run(FloatMode::Digits(3), false, third).unwrap(),
+        Word::Float(0.333)
+    );
+    // Used to wrap to a negative power of ten and give NaN.
+    match run(FloatMode::Digits(u32::MAX), false, third).unwrap() {
+        Word::Float(x) => assert!((x - 1.0 / 3.0).abs() < 1e-16, "{x}"),
+        w => panic!("unexpected {w:?}"),
+    }
+    assert_eq!(
+        run(
+            FloatMode::Digits(2),
+            false,
+            "push 0.125\npush 0.0\naddf\nhalt"
+        )
+        .unwrap(),
+        Word::Float(0.13)
+    );
+
+    let inf = "push 1.0\npush 0.0\ndivf\nhalt";
+    assert_eq!(
+        run(FloatMode::Strict, false, inf).unwrap(),
+        Word::Float(f64::INFINITY)
+    );
+    assert_eq!(
+        run(FloatMode::Strict, true, inf),
+        Err(RunOutcome::Trapped(MachineErr::NonFiniteFloat { ip: 2 }))
+    );
+}
+
+#[test]
+fn every_push_is_bounded_by_capacity() {
+    let overflows = |src: &str| {
+        let prog = asm::assemble(src).unwrap();
+        let mut m = Machine::builder().program(prog).stack_size(2).build();
+        m.run(FUEL) == RunOutcome::Trapped(MachineErr::StackOverflow)
+    };
+
+    assert!(overflows("push 1\npush 2\npush 3\nhalt"));
+    assert!(overflows("push 1\npush 2\ndup 0\nhalt"));
+    assert!(overflows("push 1\nenter 2\nhalt"));
+    assert!(overflows("push 1\nenter 1\nload_arg 0\nhalt"));
+    assert!(overflows("enter 1\npush 1\nload_local 0\nhalt"));
+
+    let mut m = Machine::new(asm::assemble("push 1\ndup 1\nhalt").unwrap());
+    assert_eq!(m.run(FUEL), RunOutcome::Trapped(MachineErr::StackUnderflow));
+}
+
+#[test]
+fn stack_grows_up_to_its_cap() {
+    let prog = asm::read_source_file("feb.vm").unwrap();
+    let mut m = Machine::builder()
+        .program(prog)
+        .stack_size(1)
+        .max_stack_size(10)
+        .build();
+
+    assert_eq!(m.run(FUEL), RunOutcome::Trapped(MachineErr::StackOverflow));
+    assert_eq!(m.sp(), 10);
+    assert_eq!(m.stack_capacity(), 10);
+    assert_eq!(m.stack()[8], Word::Int(21));
+}
+
+#[test]
+fn interrupt_stops_an_infinite_loop() {
+    let prog = asm::assemble("push 0\nloop:\npush 1\naddi\njump 1").unwrap();
+    let mut m = Machine::builder()
+        .program(prog)
+        .overflow(Overflow::Wrap)
+        .build();
+    let handle = m.interrupt_handle();
+
+    // Interrupting before `run` stops it before the first instruction.
+    handle.interrupt();
+    assert_eq!(m.run(FUEL), RunOutcome::Interrupted);
+    assert_eq!(m.ip(), 0);
+    assert_eq!(m.fuel(), FUEL);
+
+    let stopper = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        handle.interrupt();
+    });
+    assert_eq!(m.run(u64::MAX), RunOutcome::Interrupted);
+    stopper.join().unwrap();
+
+    assert!(!m.is_halted());
+    assert_eq!(m.sp(), if m.ip() == 2 { 2 } else { 1 });
+}
+
+#[test]
+fn undo_takes_back_every_kind_of_step() {
+    let src = "push 6\npush 7\ncall f\npop\nhalt\nf:\nenter 1\nload_arg 0\nload_arg 1\nmuli\nstore_local 0\nload_local 0\nstore_arg 1\nleave\nswap 0\nret";
+    let mut m = Machine::builder()
+        .program(asm::assemble(src).unwrap())
+        .stack_size(1)
+        .max_stack_size(8)
+        .build();
+
+    let mut before = vec![m.snapshot()];
+    let mut undos = Vec::new();
+    while !m.is_halted() {
+        let (result, undo) = m.step_undoable();
+        result.unwrap();
+        undos.push(undo);
+        before.push(m.snapshot());
+    }
+    assert_eq!(m.stack(), &[Word::Int(42)]);
+
+    while let Some(undo) = undos.pop() {
+        before.pop();
+        m.undo(undo);
+        assert_eq!(m.snapshot(), *before.last().unwrap());
+    }
+}
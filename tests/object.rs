@@ -0,0 +1,129 @@
+use rvm::object::{self, LinkErr, Object};
+use rvm::{asm, Ins, Machine, RunOutcome, Word};
+
+const MAIN: &str = "\
+.import square from \"math.vm\"
+push 3
+call square
+jump done
+push 0
+done:
+halt
+";
+
+const MATH: &str = "\
+.export square
+.equ TWO 2
+square:
+  dup 0
+  muli
+  jump out
+out:
+  push TWO
+  muli
+  ret
+";
+
+fn objects(files: &[(&str, &str)]) -> Vec<(String, Object)> {
+    files
+        .iter()
+        .map(|(name, src)| (name.to_string(), asm::assemble_object(name, src).unwrap()))
+        .collect()
+}
+
+#[test]
+fn objects_keep_imports_as_relocations() {
+    let main = asm::assemble_object("main.vm", MAIN).unwrap();
+    let math = asm::assemble_object("math.vm", MATH).unwrap();
+
+    assert_eq!(main.code[1], Ins::Call(0));
+    assert_eq!(main.relocations, [(1, "square".to_string())]);
+    assert!(main.symbols.is_empty());
+    assert_eq!(math.symbols, [("square".to_string(), 0)]);
+
+    let bytes = object::encode(&math).unwrap();
+    assert_eq!(object::decode(&bytes).unwrap(), math);
+    assert!(object::decode(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn labels_at_the_end_of_an_object_read_back() {
+    let o = asm::assemble_object("end.vm", ".export end\npush 1\nend:\n").unwrap();
+    assert_eq!(o.symbols, [("end".to_string(), 1)]);
+
+    let bytes = object::encode(&o).unwrap();
+    assert_eq!(object::decode(&bytes).unwrap(), o);
+}
+
+#[test]
+fn linking_relocates_every_target() {
+    let prog = object::link(&objects(&[("main.o", MAIN), ("math.o", MATH)])).unwrap();
+
+    assert_eq!(prog[1], Ins::Call(5));
+    assert_eq!(prog[2], Ins::Jump(4));
+    assert_eq!(prog[7], Ins::Jump(8));
+
+    let mut m = Machine::new(prog);
+    assert_eq!(m.run(100), RunOutcome::Halted);
+    assert_eq!(m.stack(), [Word::Int(18)]);
+}
+
+#[test]
+fn unresolved_and_duplicate_symbols_are_reported() {
+    let errors =
+        object::link(&objects(&[("main.o", MAIN), ("a.o", MATH), ("b.o", MATH)])).unwrap_err();
+    assert_eq!(
+        errors,
+        [LinkErr::Duplicate {
+            symbol: "square".to_string(),
+            first: "a.o".to_string(),
+            second: "b.o".to_string(),
+        }]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "Error: `square` is defined in both a.o and b.o"
+    );
+
+    let errors = object::link(&objects(&[("main.o", MAIN)])).unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "Error: `square` used in main.o is not defined"
+    );
+}
+
+#[test]
+fn imports_and_exports_of_objects_are_checked() {
+    let src = "\
+.import f g from \"lib.vm\"
+.export N
+.equ N 1
+push f
+jump g+1
+call f
+";
+
+    let errors = asm::assemble_object("bad.vm", src).unwrap_err();
+
+    let found = errors
+        .iter()
+        .map(|e| (e.line, e.msg.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            (
+                2,
+                "only labels can be exported from an object, `N` is not one"
+            ),
+            (
+                4,
+                "`f` uses an import of an object, which can only be the whole target of a `jump`, `jumpif` or `call`"
+            ),
+            (
+                5,
+                "`g+1` uses an import of an object, which can only be the whole target of a `jump`, `jumpif` or `call`"
+            ),
+        ]
+    );
+}
@@ -0,0 +1,81 @@
+use rvm::machine::{FloatMode, Frame, Overflow, MAX_STACK_SIZE};
+use rvm::snapshot::{self, SnapshotErr};
+use rvm::{asm, Machine, RunOutcome, Word};
+
+#[test]
+fn resumed_run_matches_an_uninterrupted_one() {
+    let prog = asm::read_source_file("e.vm").unwrap();
+    let build = || {
+        Machine::builder()
+            .program(prog.clone())
+            .float_mode(FloatMode::Digits(10))
+            .overflow(Overflow::Wrap)
+            .build()
+    };
+
+    let mut whole = build();
+    assert_eq!(whole.run(10_000), RunOutcome::Halted);
+
+    let mut first = build();
+    assert!(matches!(first.run(137), RunOutcome::OutOfFuel { .. }));
+    let bytes = snapshot::encode(&first.snapshot()).unwrap();
+
+    let mut second = Machine::new(Vec::new());
+    second.restore(snapshot::decode(&bytes).unwrap());
+    assert_eq!(second.snapshot(), first.snapshot());
+    assert_eq!(second.run(10_000 - 137), RunOutcome::Halted);
+
+    assert_eq!(second.snapshot(), whole.snapshot());
+}
+
+#[test]
+fn snapshot_keeps_frames_and_calls() {
+    let src = "push 6\ncall f\nhalt\nf:\nenter 2\nload_arg 0\nstore_local 1\nleave\nret";
+    let mut m = Machine::new(asm::assemble(src).unwrap());
+    assert!(matches!(m.run(5), RunOutcome::OutOfFuel { .. }));
+
+    let s = snapshot::decode(&snapshot::encode(&m.snapshot()).unwrap()).unwrap();
+    assert_eq!(s.calls, m.call_stack());
+    assert_eq!(s.frames, m.frames());
+    assert_eq!(s.stack, m.stack());
+    assert_eq!(s.stack[0], Word::Int(6));
+}
+
+#[test]
+fn corrupt_snapshots_are_rejected() {
+    let m = Machine::new(asm::assemble("push 1\nhalt").unwrap());
+    let mut bytes = snapshot::encode(&m.snapshot()).unwrap();
+
+    assert!(matches!(
+        snapshot::decode(&bytes[..bytes.len() - 1]),
+        Err(SnapshotErr::ChecksumMismatch { .. })
+    ));
+    assert!(matches!(
+        snapshot::decode(b"RVMB\x02\x00"),
+        Err(SnapshotErr::BadMagic)
+    ));
+
+    bytes[4] = 9;
+    assert!(matches!(
+        snapshot::decode(&bytes),
+        Err(SnapshotErr::ChecksumMismatch { .. })
+    ));
+}
+
+#[test]
+fn snapshots_of_impossible_machines_are_rejected() {
+    let m = Machine::new(asm::assemble("push 1\nhalt").unwrap());
+    let decode = |s: &snapshot::Snapshot| snapshot::decode(&snapshot::encode(s).unwrap());
+
+    // Used to overflow while checking the frame.
+    let mut s = m.snapshot();
+    s.frames.push(Frame {
+        fp: usize::MAX,
+        locals: 2,
+    });
+    assert!(matches!(decode(&s), Err(SnapshotErr::Inconsistent(_))));
+
+    let mut s = m.snapshot();
+    s.max_stack_size = MAX_STACK_SIZE + 1;
+    assert!(matches!(decode(&s), Err(SnapshotErr::Inconsistent(_))));
+}
@@ -0,0 +1,107 @@
+use rvm::asm;
+use rvm::watch::{Cmp, Condition, Operand, Trigger, Watch, Watcher};
+use rvm::{Machine, Word};
+
+fn run_until(src: &str, watches: Vec<Watch>) -> Option<Trigger> {
+    let mut m = Machine::new(asm::assemble(src).unwrap());
+    let mut w = Watcher::new(watches);
+    w.reset(&m);
+
+    while !m.is_halted() {
+        m.step().unwrap();
+        if let Some(t) = w.check(&m) {
+            return Some(t);
+        }
+    }
+
+    None
+}
+
+fn no_labels(at: &str) -> Result<usize, String> {
+    at.parse().map_err(|_| format!("no label `{at}`"))
+}
+
+#[test]
+fn conditions_parse_and_compare_across_kinds() {
+    let c = Condition::try_from("top >= 2.5").unwrap();
+    assert_eq!(c.to_string(), "top >= 2.5");
+    assert_eq!(
+        c,
+        Condition {
+            lhs: Operand::Top,
+            op: Cmp::Ge,
+            rhs: Word::Float(2.5)
+        }
+    );
+
+    assert_eq!(
+        Watch::parse("sp<=3", no_labels).unwrap().to_string(),
+        "sp <= 3"
+    );
+    assert!(Condition::try_from("top ~ 1").is_err());
+    assert!(Condition::try_from("rp == 1").is_err());
+
+    let t = run_until(
+        "push 1\npush 2\naddi\npush true\nhalt",
+        vec![Watch::When(Condition::try_from("top > 2.5").unwrap())],
+    )
+    .unwrap();
+    assert_eq!((t.step, t.ip), (3, 3));
+
+    // A boolean never compares with a number.
+    assert!(run_until(
+        "push true\nhalt",
+        vec![Watch::parse("top != 0", no_labels).unwrap()]
+    )
+    .is_none());
+}
+
+#[test]
+fn slot_watch_fires_on_change() {
+    let t = run_until(
+        "push 1\npush 2\npop\npush 2\npush 3\nhalt",
+        vec![Watch::slot("stack[1]").unwrap()],
+    )
+    .unwrap();
+
+    assert_eq!(t.step, 2);
+    assert_eq!(t.reason, "stack[1] changed from - to 2");
+}
+
+#[test]
+fn hit_counts_stop_on_the_nth_arrival() {
+    let src = std::fs::read_to_string("feb.vm").unwrap();
+    let (prog, info) = asm::assemble_with_debug_info("feb.vm", &src).unwrap();
+    let len = prog.len();
+    let mut m = Machine::builder().program(prog).max_stack_size(100).build();
+
+    let hits = Watch::hits("loop:3", |at| info.location(at, len)).unwrap();
+    assert_eq!(hits, Watch::Hits { ip: 2, count: 3 });
+    assert!(Watch::hits("loop:0", |at| info.location(at, len)).is_err());
+    assert!(Watch::hits("nowhere", |at| info.location(at, len)).is_err());
+
+    let mut w = Watcher::new(vec![hits]);
+    w.reset(&m);
+    let t = loop {
+        m.step().unwrap();
+        if let Some(t) = w.check(&m) {
+            break t;
+        }
+    };
+
+    // Two instructions to set up, then four per trip round the loop.
+    assert_eq!(t.step, 2 + 4 * 2);
+    assert_eq!(m.stack(), &[0, 1, 1, 2].map(Word::Int));
+}
+
+#[test]
+fn starting_on_the_instruction_is_not_a_hit() {
+    let src = "loop:\npush 1\npop\njump loop";
+
+    let t = run_until(src, vec![Watch::Hits { ip: 0, count: 1 }]).unwrap();
+    assert_eq!((t.step, t.ip), (3, 0));
+    assert_eq!(t.reason, "reached 0 for the 1st time");
+
+    let t = run_until(src, vec![Watch::Hits { ip: 0, count: 2 }]).unwrap();
+    assert_eq!(t.step, 6);
+}